@@ -0,0 +1,192 @@
+//! Attribute macros of `pyapp`, re-exported by it
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2, TokenTree};
+use quote::quote;
+use syn::{
+    meta::ParseNestedMeta, parse_macro_input, Attribute, FnArg, GenericArgument, Item,
+    ItemFn, LitStr, PathArguments, ReturnType, Type,
+};
+
+/// Add a `#[pyfunction]` or `#[pyclass]` to the module at startup, placed
+/// above the attribute of pyo3:
+///
+/// ```ignore
+/// #[pyapp::export(module = "foo.bar")]
+/// #[pyfunction]
+/// fn add_one(x: i64) -> i64 {
+///     x + 1
+/// }
+/// ```
+///
+/// The parent modules are created as needed, and the type hints of functions
+/// are used by `--stubgen`.
+#[proc_macro_attribute]
+pub fn export(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut module = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("module") {
+            module = Some(meta.value()?.parse::<LitStr>()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `module = \"...\"`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+    let item = parse_macro_input!(item as Item);
+    let res = match module {
+        Some(module) => expand(&module, &item),
+        None => Err(syn::Error::new(Span::call_site(), "expected `module = \"...\"`")),
+    };
+    res.unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+fn expand(module: &LitStr, item: &Item) -> syn::Result<TokenStream2> {
+    let (add, hint) = match item {
+        Item::Fn(f) => {
+            let ident = &f.sig.ident;
+            let hint = hint(f)?;
+            (
+                quote! { m.add_function(::pyapp::pyo3::wrap_pyfunction!(#ident, m)?) },
+                quote! { ::core::option::Option::Some({
+                    fn hint() -> ::pyapp::FnHint {
+                        #hint
+                    }
+                    hint
+                }) },
+            )
+        }
+        Item::Struct(syn::ItemStruct { ident, .. })
+        | Item::Enum(syn::ItemEnum { ident, .. }) => {
+            (quote! { m.add_class::<#ident>() }, quote! { ::core::option::Option::None })
+        }
+        _ => {
+            return Err(syn::Error::new_spanned(
+                item,
+                "expected a `#[pyfunction]` or a `#[pyclass]`",
+            ))
+        }
+    };
+    Ok(quote! {
+        #item
+        ::pyapp::inventory::submit! {
+            ::pyapp::Export::new(
+                #module,
+                {
+                    fn add(
+                        m: &::pyapp::pyo3::Bound<'_, ::pyapp::pyo3::types::PyModule>,
+                    ) -> ::pyapp::pyo3::PyResult<()> {
+                        use ::pyapp::pyo3::types::PyModuleMethods;
+                        #add
+                    }
+                    add
+                },
+                #hint,
+            )
+        }
+    })
+}
+
+/// `FnHint` of `f`, arguments with lifetimes are `Any`
+fn hint(f: &ItemFn) -> syn::Result<TokenStream2> {
+    let type_info = quote! { ::pyapp::pyo3::inspect::types::TypeInfo };
+    let name = match python_name(&f.attrs)? {
+        Some(name) => name,
+        None => f.sig.ident.to_string(),
+    };
+    let args = f
+        .sig
+        .inputs
+        .iter()
+        .filter_map(|arg| match arg {
+            FnArg::Typed(arg) => Some(&*arg.ty),
+            FnArg::Receiver(_) => None,
+        })
+        .filter(|ty| !is_python(ty))
+        .map(|ty| match ty {
+            Type::Reference(r) if matches!(&*r.elem, Type::Path(p) if p.path.is_ident("str")) => {
+                quote! { #type_info::builtin("str") }
+            }
+            _ if has_lifetime(ty) => quote! { #type_info::Any },
+            _ => quote! { <#ty as ::pyapp::pyo3::FromPyObject>::type_input() },
+        });
+    let ret = match &f.sig.output {
+        ReturnType::Type(_, ty) => match ok_type(ty) {
+            Type::Tuple(t) if t.elems.is_empty() => quote! { #type_info::None },
+            ty if has_lifetime(ty) => quote! { #type_info::Any },
+            ty => quote! {
+                <#ty as ::pyapp::pyo3::IntoPy<::pyapp::pyo3::PyObject>>::type_output()
+            },
+        },
+        ReturnType::Default => quote! { #type_info::None },
+    };
+    Ok(quote! { ::pyapp::FnHint::new(#name, vec![#(#args),*], #ret) })
+}
+
+/// `name` of `#[pyo3(name = "...")]` or `#[pyfunction(name = "...")]`
+fn python_name(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    let mut name = None;
+    for attr in attrs {
+        if !(attr.path().is_ident("pyo3") || attr.path().is_ident("pyfunction"))
+            || !matches!(attr.meta, syn::Meta::List(_))
+        {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                skip_option(&meta)
+            }
+        })?;
+    }
+    Ok(name)
+}
+
+/// Skip the other options, e.g. `signature = (x, /, *args)`
+fn skip_option(meta: &ParseNestedMeta) -> syn::Result<()> {
+    meta.input.step(|cursor| {
+        let mut rest = *cursor;
+        while let Some((tt, next)) = rest.token_tree() {
+            if matches!(&tt, TokenTree::Punct(p) if p.as_char() == ',') {
+                break;
+            }
+            rest = next;
+        }
+        Ok(((), rest))
+    })
+}
+
+/// `Python<'py>`, which is not a parameter of the python function
+fn is_python(ty: &Type) -> bool {
+    matches!(ty, Type::Path(p) if p.path.segments.last().is_some_and(|s| s.ident == "Python"))
+}
+
+/// `T` of `PyResult<T>` or `Result<T, E>`
+fn ok_type(ty: &Type) -> &Type {
+    if let Type::Path(p) = ty {
+        if let Some(segment) = p.path.segments.last() {
+            if segment.ident == "PyResult" || segment.ident == "Result" {
+                if let PathArguments::AngleBracketed(args) = &segment.arguments {
+                    if let Some(GenericArgument::Type(ty)) = args.args.first() {
+                        return ty;
+                    }
+                }
+            }
+        }
+    }
+    ty
+}
+
+/// References and types like `Bound<'py, T>` can not be named in the hint
+fn has_lifetime(ty: &Type) -> bool {
+    fn walk(tokens: TokenStream2) -> bool {
+        tokens.into_iter().any(|tt| match tt {
+            TokenTree::Punct(p) => p.as_char() == '\'' || p.as_char() == '&',
+            TokenTree::Group(g) => walk(g.stream()),
+            _ => false,
+        })
+    }
+    walk(quote! { #ty })
+}
@@ -1,67 +1,184 @@
 use crate::{
-    args, py, BLANK_COLOR, BRACKET_COLORS, CLASS_COLOR, COMMENT_COLOR, FUNCTION_COLOR,
-    KEY1_COLOR, KEY2_COLOR, PROMPT1, PROMPT1_ERR, PROMPT1_OK, PROMPT2, PROMPT2_OK,
-    STRING_COLOR, SYMBOL_COLOR, TERMINATE_N, UNKNOWN_COLOR,
+    args, completion,
+    config::{self, Config, KeyAction},
+    display, export, fuzzy, highlight, indent, kernel, keys, magic, paste, py, signature,
+    stubgen,
+    theme::{self, Theme},
+    traceback,
 };
-use anstyle::{AnsiColor, Style};
+use anstyle::Style;
 use pyo3::{
-    types::{IntoPyDict, PyAnyMethods, PyModule},
-    PyErr, Python,
+    exceptions::PySystemExit,
+    types::{PyAny, PyAnyMethods, PyModule},
+    Bound, PyErr, Python,
 };
 use ruff_python_ast::Mod;
-use ruff_python_parser::{LexicalErrorType, ParseErrorType, Parsed, TokenKind};
+use ruff_python_parser::Parsed;
 use ruff_text_size::TextRange;
 use rustyline::{
     completion::Completer,
+    config::Configurer,
     error::ReadlineError,
     highlight::{DisplayOnce, Highlighter, Style as _, StyledBlocks},
-    hint::Hinter,
+    hint::{Hint, Hinter},
     history::DefaultHistory,
     validate::{ValidationContext, ValidationResult, Validator},
-    Cmd, Editor, EventHandler, Helper, KeyCode, KeyEvent, Modifiers, Movement,
+    Cmd, ConditionalEventHandler, Editor, Event, EventContext, EventHandler, Helper,
+    KeyCode, KeyEvent, Modifiers, Movement, RepeatCount,
 };
 use std::{
     fs::File,
-    io::{BufRead, BufReader, Read},
-    iter::once,
+    io::{BufRead, BufReader, IsTerminal, Read},
     marker::PhantomData,
-    path::PathBuf,
+    ops::Range,
+    path::{Path, PathBuf},
 };
 use thiserror::Error;
 
+/// Register `modules`, then run in the mode of `args` with `base` under the
+/// config file
 #[inline]
-pub(super) fn run(args: args::Args) -> ExitCode {
-    use py::foo;
-    pyo3::append_to_inittab!(foo);
-    pyo3::prepare_freethreaded_python();
-    match args.mode {
+pub(crate) fn run(args: args::Args, modules: &[py::Module], base: Config) -> ExitCode {
+    let stdin_is_tty = std::io::stdin().is_terminal();
+    // only the shell says goodbye, the other modes print what python does
+    let shell = match &args.mode {
+        args::Mode::InteractiveShell | args::Mode::Stdin(_) => stdin_is_tty,
+        args::Mode::ExecFile(_) | args::Mode::ExecModule(_) | args::Mode::Command(..) => {
+            args.flag.inspect
+        }
+        args::Mode::StubGen(_) | args::Mode::Kernel(_) => false,
+    };
+    let config = match Config::load(&args.flag, base.clone()) {
+        Ok(config) => config,
+        // the config file is for the shell and the kernel, it can't stop a program
+        Err(e) if !(shell || matches!(args.mode, args::Mode::Kernel(_))) => {
+            eprintln!("{e}, ignored");
+            base
+        }
+        Err(e) => return ExitCode { inner: Err(e.into()), path: None, shell: false },
+    };
+    py::prepare_freethreaded_python(&args.flag);
+    // a bad theme only fails the shell, the tracebacks fall back to the default
+    let theme = Theme::load(&config.theme).unwrap_or_default();
+    if let Err(e) = Python::with_gil(|py| py::register(py, modules)) {
+        Python::with_gil(|py| traceback::print(py, &e, &theme, config.full_traceback));
+        return ExitCode { inner: Err(e.into()), path: None, shell: false };
+    }
+    let mut exit_code = match args.mode {
+        args::Mode::InteractiveShell if stdin_is_tty => ExitCode {
+            inner: run_shell(&config, config.init_cmds.clone(), false),
+            path: None,
+            shell,
+        },
         args::Mode::InteractiveShell => ExitCode {
-            inner: run_shell(vec![
-                "# let's import a python module that impl by Rust!".to_owned(),
-                "import foo".to_owned(),
-            ]),
+            inner: run_stdin(&vec!["-".to_owned()]),
+            path: None,
+            shell,
+        },
+        args::Mode::Stdin(py_args) if stdin_is_tty => ExitCode {
+            inner: Python::with_gil(|py| py::import_args(py, &py_args))
+                .map_err(Into::into)
+                .and_then(|()| run_shell(&config, config.init_cmds.clone(), false)),
             path: None,
+            shell,
         },
+        args::Mode::Stdin(py_args) => {
+            ExitCode { inner: run_stdin(&py_args), path: None, shell }
+        }
         args::Mode::ExecFile(py_args) => ExitCode {
-            inner: if args.flag.quiet {
-                quiet_exec_file(&py_args)
-            } else {
-                exec_file(&py_args)
-            },
+            inner: inspect(
+                &args.flag,
+                &config,
+                &theme,
+                if args.flag.quiet {
+                    quiet_exec_file(&py_args)
+                } else {
+                    exec_file(&py_args, &config.prompt)
+                },
+            ),
             path: Some((&py_args[0]).into()),
+            shell,
         },
-        args::Mode::ExecModule(py_args) => {
-            ExitCode { inner: run_module(&py_args), path: None }
-        }
-        args::Mode::Command(cmd, py_args) => {
-            ExitCode { inner: run_command(&cmd, &py_args), path: None }
+        args::Mode::ExecModule(py_args) => ExitCode {
+            inner: inspect(&args.flag, &config, &theme, run_module(&py_args)),
+            path: None,
+            shell,
+        },
+        args::Mode::Command(cmd, py_args) => ExitCode {
+            inner: inspect(&args.flag, &config, &theme, run_command(&cmd, &py_args)),
+            path: None,
+            shell,
+        },
+        args::Mode::StubGen(dir) => ExitCode {
+            inner: write_stubs(&dir, modules),
+            path: Some(dir),
+            shell,
+        },
+        args::Mode::Kernel(file) => ExitCode {
+            inner: kernel::run(&file, &config, theme.clone()).map_err(Into::into),
+            path: None,
+            shell,
+        },
+    };
+    if let Err(ExecErr::PyResult(e)) = &exit_code.inner {
+        match Python::with_gil(|py| system_exit_code(py, e)) {
+            Some(code) => exit_code.inner = Err(ExecErr::Exit(code)),
+            None => Python::with_gil(|py| {
+                traceback::print(py, e, &theme, config.full_traceback)
+            }),
         }
     }
+    // the interpreter is never finalized, so flush what python still buffers
+    Python::with_gil(py::flush_stdio);
+    exit_code
+}
+
+/// Exit code of `exit(code)` in a program like python, where `None` is 0 and
+/// another value is printed for 1
+fn system_exit_code(py: Python, e: &PyErr) -> Option<u8> {
+    if !e.is_instance_of::<PySystemExit>(py) {
+        return None;
+    }
+    let code = e.value_bound(py).getattr("code").ok()?;
+    Some(if code.is_none() {
+        0
+    } else if let Ok(code) = code.extract::<i64>() {
+        code as u8
+    } else {
+        eprintln!("{code}");
+        1
+    })
 }
 
-pub(crate) struct ExitCode {
+/// Enter the shell in the same `__main__` after a file, module or command
+/// when `-i` is set, with the traceback printed if it raised
+#[inline]
+fn inspect(
+    flag: &args::Flag,
+    config: &Config,
+    theme: &Theme,
+    res: Result<(), ExecErr>,
+) -> Result<(), ExecErr> {
+    if !flag.inspect {
+        return res;
+    }
+    let on_error = match res {
+        Ok(()) => false,
+        Err(ExecErr::PyResult(e)) => {
+            Python::with_gil(|py| traceback::print(py, &e, theme, config.full_traceback));
+            true
+        }
+        Err(e) => return Err(e),
+    };
+    run_shell(config, vec![], on_error)
+}
+
+/// Result of the shell, reported as the exit code of the process
+pub struct ExitCode {
     inner: Result<(), ExecErr>,
     path: Option<PathBuf>,
+    /// the shell ran, which says `Exiting..` at the end
+    shell: bool,
 }
 
 #[derive(Error, Debug)]
@@ -74,34 +191,77 @@ enum ExecErr {
     IO(#[from] std::io::Error),
     #[error("fmt error {0}")]
     Fmt(#[from] core::fmt::Error),
+    #[error("config error {0}")]
+    Config(#[from] config::ConfigError),
+    #[error("ShellBuilder: {0}")]
+    Builder(config::InvalidConfig),
+    #[error("theme error {0}")]
+    Theme(#[from] theme::ThemeError),
+    #[error("kernel error {0}")]
+    Kernel(#[from] kernel::KernelError),
     #[error("exit with code {0}")]
     Exit(u8),
 }
 
+impl ExitCode {
+    /// Exited without error, or by `exit(0)`
+    #[inline]
+    pub fn is_success(&self) -> bool {
+        matches!(self.inner, Ok(()) | Err(ExecErr::Exit(0)))
+    }
+    /// The settings of `ShellBuilder` can't run
+    #[inline]
+    pub(crate) fn invalid_builder(e: config::InvalidConfig) -> Self {
+        Self {
+            inner: Err(ExecErr::Builder(e)),
+            path: None,
+            shell: false,
+        }
+    }
+}
+
 impl std::process::Termination for ExitCode {
     #[inline]
     fn report(self) -> std::process::ExitCode {
         match self.inner {
             Ok(()) => {
-                println!("\nExiting...");
+                if self.shell {
+                    println!("\nExiting...");
+                }
                 0.into()
             }
             Err(ExecErr::Exit(code)) => {
-                println!("Exiting..");
+                if self.shell {
+                    println!("Exiting..");
+                }
                 code.into()
             }
-            Err(ExecErr::PyResult(e)) => {
+            // the traceback is printed by `run`
+            Err(ExecErr::PyResult(_)) => 1.into(),
+            Err(ExecErr::Readline(e)) => {
                 println!("{}", e);
                 1.into()
             }
-            Err(ExecErr::Readline(e)) => {
+            Err(ExecErr::Fmt(e)) => {
                 println!("{}", e);
                 1.into()
             }
-            Err(ExecErr::Fmt(e)) => {
+            Err(ExecErr::Config(e)) => {
+                println!("{}", e);
+                1.into()
+            }
+            Err(e @ ExecErr::Builder(_)) => {
+                println!("{}", e);
+                1.into()
+            }
+            Err(ExecErr::Theme(e)) => {
                 println!("{}", e);
                 1.into()
             }
+            Err(ExecErr::Kernel(e)) => {
+                eprintln!("{}", e);
+                1.into()
+            }
             Err(ExecErr::IO(e)) => {
                 if let Some(path) = self.path {
                     println!("{}: {}", path.display(), e);
@@ -116,20 +276,46 @@ impl std::process::Termination for ExitCode {
 
 struct MyHelper {
     parsed: Parsed<Mod>,
-    bracket_level_diff: i32,
+    modules: completion::ModuleCache,
+    syntax: highlight::Syntax,
+    /// bracket pair under the cursor when last rendered
+    cursor_brackets: Option<(usize, usize)>,
+    /// syntax errors to underline, and the message of the first one
+    errors: Vec<TextRange>,
+    error: Option<String>,
+    /// the hint shown is `error`
+    error_hint: bool,
+    /// the current parameter in the signature hint shown
+    hint_bold: Option<Range<usize>>,
     need_render: bool,
     on_error: bool,
+    prompt: config::Prompt,
+    theme: Theme,
+    /// indent unit, spaces or a tab
+    indent: String,
+    /// `ps2_ok` printed at each newline of the cell
+    newline_ps2_ok: String,
 }
 
 impl MyHelper {
     #[inline]
-    fn new() -> Self {
+    fn new(prompt: &config::Prompt, theme: Theme, indent: &str) -> Self {
         use ruff_python_parser::{parse_unchecked, Mode};
         Self {
-            parsed: parse_unchecked("", Mode::Module),
+            parsed: parse_unchecked("", Mode::Ipython),
+            modules: completion::ModuleCache::new(),
             on_error: false,
+            newline_ps2_ok: format!("\n{}", prompt.ps2_ok),
+            prompt: prompt.clone(),
+            theme,
+            indent: indent.to_owned(),
             need_render: true,
-            bracket_level_diff: 0,
+            syntax: highlight::Syntax::default(),
+            cursor_brackets: None,
+            errors: Vec::new(),
+            error: None,
+            error_hint: false,
+            hint_bold: None,
         }
     }
 }
@@ -137,70 +323,57 @@ impl MyHelper {
 impl Helper for MyHelper {
     fn update_after_edit(&mut self, line: &str, _pos: usize, _forced_refresh: bool) {
         use ruff_python_parser::{parse_unchecked, Mode};
-        self.parsed = parse_unchecked(line, Mode::Module);
-        self.bracket_level_diff =
-            self.parsed
-                .tokens()
-                .iter()
-                .fold(0, |level, token| match token.kind() {
-                    TokenKind::Lpar | TokenKind::Lsqb | TokenKind::Lbrace => level + 1,
-                    TokenKind::Rpar | TokenKind::Rsqb | TokenKind::Rbrace => level - 1,
-                    _ => level,
-                });
+        // IPython mode to lex the magics as `IpyEscapeCommand`
+        self.parsed = parse_unchecked(line, Mode::Ipython);
+        self.syntax = highlight::Syntax::new(&self.parsed);
+        self.errors.clear();
+        self.error = None;
+        // a pasted session is checked without its prompts when run
+        if !paste::has_prompts(line, &self.prompt.ps1) {
+            for error in indent::errors(&self.parsed, line) {
+                self.error.get_or_insert_with(|| error.error.to_string());
+                self.errors.push(error.location);
+            }
+        }
         self.need_render = true;
     }
     fn continuation_prompt_width<'b, 's: 'b, 'p: 'b>(
         &'s self,
         _prompt: &'p str,
     ) -> usize {
-        8
+        self.prompt.ps2.chars().count()
     }
 }
 
 impl Validator for MyHelper {
     fn validate(
         &mut self,
-        _ctx: &mut ValidationContext,
+        ctx: &mut ValidationContext,
     ) -> rustyline::Result<ValidationResult> {
-        let mut indent = self.bracket_level_diff.try_into().unwrap_or(0);
-        let mut incomplete = false;
-        let mut tokens_rev = self.parsed.tokens().iter().rev();
-        while let Some(token) = tokens_rev.next() {
-            let (kind, range) = token.as_tuple();
-            match kind {
-                TokenKind::Dedent => {
-                    indent += 1;
-                    incomplete = true;
-                }
-                TokenKind::NonLogicalNewline | TokenKind::Newline => {
-                    if incomplete {
-                        incomplete = range.len().to_u32() == 0
-                    }
-                    break;
-                }
-                _ => break,
-            }
-        }
-        for error in self.parsed.errors() {
-            match &error.error {
-                ParseErrorType::OtherError(s) => {
-                    if s.starts_with("Expected an indented") {
-                        incomplete = true;
-                        indent += 1;
-                        break;
-                    }
-                }
-                ParseErrorType::Lexical(
-                    LexicalErrorType::Eof | LexicalErrorType::LineContinuationError,
-                ) => {
-                    incomplete = true;
-                    break;
-                }
-                _ => {}
-            }
-        }
-        if incomplete {
-            Ok(ValidationResult::Incomplete(indent * 2))
+        use ruff_python_parser::{parse_unchecked, Mode};
+        let input = ctx.input();
+        if let Some(cell) =
+            paste::strip_prompts(input, &self.prompt.ps1, &self.prompt.ps2)
+        {
+            // a pasted session is complete as the cell it runs
+            Ok(if indent::is_incomplete(&parse_unchecked(&cell, Mode::Ipython)) {
+                ValidationResult::Incomplete(0)
+            } else {
+                ValidationResult::Valid(None)
+            })
+        } else if indent::is_incomplete(&self.parsed) {
+            // the Enter handler indents with tabs, rustyline only with spaces
+            let (_, next) = indent::newline(self.parsed.tokens(), input, &self.indent);
+            Ok(ValidationResult::Incomplete(if next.contains('\t') {
+                0
+            } else {
+                next.len()
+            }))
+        } else if input.starts_with("%%")
+            && !(input.ends_with('\n') && input.trim_end().contains('\n'))
+        {
+            // the body of a cell magic ends with an empty line
+            Ok(ValidationResult::Incomplete(0))
         } else {
             Ok(ValidationResult::Valid(None))
         }
@@ -208,14 +381,134 @@ impl Validator for MyHelper {
 }
 impl Completer for MyHelper {
     type Candidate = String;
+    fn complete(
+        &mut self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        if line[..pos].starts_with('%') && !line[..pos].contains(char::is_whitespace) {
+            return Ok((0, magic::complete(&line[..pos])));
+        }
+        Ok(match completion::context(&self.parsed, line, pos) {
+            Some((start, ctx)) => (
+                start,
+                Python::with_gil(|py| {
+                    self.modules.refresh(py);
+                    completion::candidates(py, &ctx, &line[start..pos], &self.modules)
+                }),
+            ),
+            None => (pos, Vec::new()),
+        })
+    }
+}
+
+/// Tab completes after an identifier or `.`, and indents otherwise
+struct TabHandler(Cmd);
+
+impl ConditionalEventHandler for TabHandler {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        if completion::tab_completes(ctx.line(), ctx.pos()) {
+            Some(Cmd::Complete)
+        } else {
+            Some(self.0.clone())
+        }
+    }
+}
+
+/// Enter at the end of an incomplete cell starts the next line indented,
+/// after moving `else` and the like back to their block, with the indent unit
+/// and `ps1` to run a pasted session as is
+struct EnterHandler(String, String);
+
+impl ConditionalEventHandler for EnterHandler {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        use ruff_python_parser::{parse_unchecked, Mode};
+        let line = ctx.line();
+        if ctx.pos() < line.len()
+            || line.starts_with("%%")
+            || paste::has_prompts(line, &self.1)
+        {
+            return None;
+        }
+        let parsed = parse_unchecked(line, Mode::Ipython);
+        if !indent::is_incomplete(&parsed) {
+            return None;
+        }
+        Some(match indent::newline(parsed.tokens(), line, &self.0) {
+            (Some(current), next) => Cmd::Replace(
+                Movement::BeginningOfLine,
+                Some(format!("{current}\n{next}")),
+            ),
+            (None, next) => Cmd::Insert(1, format!("\n{next}")),
+        })
+    }
+}
+
+/// Hint after the cell: the rest of a history entry, or the signature of the
+/// call at the cursor or a syntax error that are not completed by the right arrow
+enum CellHint {
+    History(String),
+    Signature(String),
+    Error(String),
+}
+
+impl Hint for CellHint {
+    fn display(&self) -> &str {
+        match self {
+            Self::History(s) | Self::Signature(s) | Self::Error(s) => s,
+        }
+    }
+    fn completion(&self) -> Option<&str> {
+        match self {
+            Self::History(s) => Some(s),
+            Self::Signature(_) | Self::Error(_) => None,
+        }
+    }
 }
+
 impl Hinter for MyHelper {
-    type Hint = String;
+    type Hint = CellHint;
     fn hint(
         &mut self,
         line: &str,
         pos: usize,
         ctx: &rustyline::Context<'_>,
+    ) -> Option<CellHint> {
+        self.hint_bold = None;
+        let signature =
+            Python::with_gil(|py| signature::hint(py, &self.parsed, line, pos));
+        let hint = if let Some((signature, bold)) = signature {
+            self.hint_bold = bold.map(|bold| bold.start + 2..bold.end + 2);
+            Some(CellHint::Signature(format!("  {signature}")))
+        } else {
+            self.history_hint(line, pos, ctx).map(CellHint::History).or_else(|| {
+                self.error.as_ref().map(|error| CellHint::Error(format!("  {error}")))
+            })
+        };
+        self.error_hint = matches!(hint, Some(CellHint::Error(_)));
+        hint
+    }
+}
+
+impl MyHelper {
+    fn history_hint(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &rustyline::Context<'_>,
     ) -> Option<String> {
         use rustyline::history::SearchDirection;
         if line.is_empty() || pos < line.len() {
@@ -241,196 +534,31 @@ impl Hinter for MyHelper {
 }
 
 impl Highlighter for MyHelper {
-    fn highlight_char(&mut self, _line: &str, _pos: usize, _forced: bool) -> bool {
+    fn highlight_char(&mut self, _line: &str, pos: usize, _forced: bool) -> bool {
+        // moving onto or off a bracket changes the underlined pair
+        let cursor_brackets = self.syntax.brackets.at(self.parsed.tokens(), pos);
+        if cursor_brackets != self.cursor_brackets {
+            self.cursor_brackets = cursor_brackets;
+            self.need_render = true;
+        }
         self.need_render
     }
     #[inline]
     fn highlight<'b, 's: 'b, 'l: 'b>(
         &'s mut self,
         line: &'l str,
-        _pos: usize,
+        pos: usize,
     ) -> impl 'b + DisplayOnce {
         self.need_render = false;
-        let tokens = self.parsed.tokens();
-        let bracket_level_diff = self.bracket_level_diff;
-        let mut last_end = 0;
-        let mut bracket_level: i32 = 0;
-        let mut last_kind = TokenKind::Name;
-        let iter = tokens
-            .iter()
-            .enumerate()
-            .filter_map(|(idx, token)| {
-                let (kind, range) = token.as_tuple();
-                if range.len().to_u32() == 0 {
-                    None
-                } else {
-                    Some((idx, kind, range))
-                }
-            })
-            .chain(once((
-                0,
-                TokenKind::EndOfFile,
-                TextRange::new((line.len() as u32).into(), (line.len() as u32).into()),
-            )))
-            .flat_map(move |(idx, kind, range)| {
-                let term = match kind {
-                    TokenKind::Newline | TokenKind::NonLogicalNewline => PROMPT2_OK,
-                    _ => &line[range],
-                };
-                let style = match kind {
-                    TokenKind::Name => match last_kind {
-                        TokenKind::Def => Style::new().fg_color(Some(FUNCTION_COLOR)),
-                        TokenKind::Class => Style::new().fg_color(Some(CLASS_COLOR)),
-                        _ => match term {
-                            "self" | "super" => Style::new().fg_color(Some(KEY1_COLOR)),
-                            _ => {
-                                if term.chars().all(|c| c.is_ascii_uppercase()) {
-                                    Style::new().fg_color(Some(KEY1_COLOR))
-                                } else {
-                                    if let Some(next_token) = tokens.get(idx + 1) {
-                                        match next_token.kind() {
-                                            TokenKind::Lpar => Style::new()
-                                                .fg_color(Some(FUNCTION_COLOR)),
-                                            _ => Style::new().fg_color(Some(BLANK_COLOR)),
-                                        }
-                                    } else {
-                                        Style::new().fg_color(Some(BLANK_COLOR))
-                                    }
-                                }
-                            }
-                        },
-                    },
-                    TokenKind::Lpar | TokenKind::Lsqb | TokenKind::Lbrace => {
-                        let style = Style::new().fg_color(Some(
-                            if bracket_level_diff <= bracket_level + 1 {
-                                TryInto::<usize>::try_into(bracket_level)
-                                    .map_or(UNKNOWN_COLOR, |level| {
-                                        BRACKET_COLORS[level % BRACKET_COLORS.len()]
-                                    })
-                            } else {
-                                UNKNOWN_COLOR
-                            },
-                        ));
-                        bracket_level += 1;
-                        style
-                    }
-                    TokenKind::Rpar | TokenKind::Rsqb | TokenKind::Rbrace => {
-                        bracket_level -= 1;
-                        let style = Style::new().fg_color(Some(
-                            TryInto::<usize>::try_into(bracket_level)
-                                .map_or(UNKNOWN_COLOR, |level| {
-                                    BRACKET_COLORS[level % BRACKET_COLORS.len()]
-                                }),
-                        ));
-                        style
-                    }
-                    TokenKind::From
-                    | TokenKind::Import
-                    | TokenKind::Def
-                    | TokenKind::Class
-                    | TokenKind::Equal
-                    | TokenKind::EqEqual
-                    | TokenKind::NotEqual
-                    | TokenKind::LessEqual
-                    | TokenKind::GreaterEqual
-                    | TokenKind::DoubleStarEqual
-                    | TokenKind::PlusEqual
-                    | TokenKind::MinusEqual
-                    | TokenKind::StarEqual
-                    | TokenKind::SlashEqual
-                    | TokenKind::PercentEqual
-                    | TokenKind::AmperEqual
-                    | TokenKind::VbarEqual
-                    | TokenKind::CircumflexEqual
-                    | TokenKind::LeftShiftEqual
-                    | TokenKind::RightShiftEqual
-                    | TokenKind::DoubleSlash
-                    | TokenKind::DoubleSlashEqual
-                    | TokenKind::ColonEqual
-                    | TokenKind::At
-                    | TokenKind::AtEqual
-                    | TokenKind::Elif
-                    | TokenKind::Else
-                    | TokenKind::For
-                    | TokenKind::If
-                    | TokenKind::In
-                    | TokenKind::Plus
-                    | TokenKind::Minus
-                    | TokenKind::Star
-                    | TokenKind::Slash
-                    | TokenKind::Vbar
-                    | TokenKind::Amper
-                    | TokenKind::Less
-                    | TokenKind::Greater
-                    | TokenKind::Percent
-                    | TokenKind::Tilde
-                    | TokenKind::CircumFlex
-                    | TokenKind::LeftShift
-                    | TokenKind::RightShift
-                    | TokenKind::Dot
-                    | TokenKind::DoubleStar
-                    | TokenKind::As
-                    | TokenKind::Assert
-                    | TokenKind::Async
-                    | TokenKind::Await
-                    | TokenKind::Break
-                    | TokenKind::Continue
-                    | TokenKind::Del
-                    | TokenKind::Except
-                    | TokenKind::Global
-                    | TokenKind::Is
-                    | TokenKind::Lambda
-                    | TokenKind::Finally
-                    | TokenKind::Nonlocal
-                    | TokenKind::Not
-                    | TokenKind::Pass
-                    | TokenKind::Raise
-                    | TokenKind::Return
-                    | TokenKind::Try
-                    | TokenKind::While
-                    | TokenKind::With
-                    | TokenKind::Yield
-                    | TokenKind::Case
-                    | TokenKind::And
-                    | TokenKind::Or
-                    | TokenKind::Match => Style::new().fg_color(Some(KEY2_COLOR)),
-                    TokenKind::String
-                    | TokenKind::FStringStart
-                    | TokenKind::FStringMiddle
-                    | TokenKind::FStringEnd => Style::new().fg_color(Some(STRING_COLOR)),
-                    TokenKind::Int
-                    | TokenKind::Float
-                    | TokenKind::Complex
-                    | TokenKind::Ellipsis
-                    | TokenKind::True
-                    | TokenKind::False
-                    | TokenKind::None
-                    | TokenKind::Type => Style::new().fg_color(Some(KEY1_COLOR)),
-                    TokenKind::Comment => {
-                        Style::new().fg_color(Some(COMMENT_COLOR)).italic()
-                    }
-                    TokenKind::Comma
-                    | TokenKind::Unknown
-                    | TokenKind::IpyEscapeCommand
-                    | TokenKind::Exclamation
-                    | TokenKind::Colon => Style::new().fg_color(Some(BLANK_COLOR)),
-                    TokenKind::Indent
-                    | TokenKind::Dedent
-                    | TokenKind::Newline
-                    | TokenKind::NonLogicalNewline
-                    | TokenKind::EndOfFile => Style::new(),
-                    TokenKind::Semi | TokenKind::Question | TokenKind::Rarrow => {
-                        Style::new().fg_color(Some(SYMBOL_COLOR)).italic()
-                    }
-                };
-                last_kind = kind;
-                let out =
-                    core::iter::once((style, &line[last_end..range.start().to_usize()]))
-                        .chain(core::iter::once((style, term)));
-                last_end = range.end().to_usize();
-                out
-            });
-        StyledBlocks::new(iter)
+        StyledBlocks::new(highlight::styled(
+            self.parsed.tokens(),
+            line,
+            &self.theme,
+            &self.newline_ps2_ok,
+            &self.syntax,
+            Some(pos),
+            &self.errors,
+        ))
     }
     #[inline]
     fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
@@ -440,9 +568,9 @@ impl Highlighter for MyHelper {
     ) -> impl 'b + DisplayOnce {
         if default {
             if self.on_error {
-                PROMPT1_ERR
+                self.prompt.ps1_err.as_str()
             } else {
-                PROMPT1_OK
+                self.prompt.ps1_ok.as_str()
             }
         } else {
             prompt
@@ -458,7 +586,10 @@ impl Highlighter for MyHelper {
             I: Iterator<Item = &'l str>,
         {
             style: Style,
+            /// range of the first line in bold
+            bold: Option<Range<usize>>,
             iter: I,
+            ps2: &'l str,
             _marker: PhantomData<&'l ()>,
         }
         impl<'l, I> DisplayOnce for Lines<'l, I>
@@ -469,8 +600,21 @@ impl Highlighter for MyHelper {
                 let mut iter = self.iter;
                 if let Some(first_line) = iter.next() {
                     write!(f, "{}", self.style.start())?;
-                    write!(f, "{}", first_line)?;
-                    iter.map(|line| write!(f, "\n{}{}", PROMPT2, line))
+                    match self.bold.filter(|bold| first_line.get(bold.clone()).is_some())
+                    {
+                        Some(bold) => write!(
+                            f,
+                            "{}{}{}{}{}{}",
+                            &first_line[..bold.start],
+                            self.style.bold().start(),
+                            &first_line[bold.clone()],
+                            self.style.end(),
+                            self.style.start(),
+                            &first_line[bold.end..]
+                        )?,
+                        None => write!(f, "{}", first_line)?,
+                    }
+                    iter.map(|line| write!(f, "\n{}{}", self.ps2, line))
                         .collect::<core::fmt::Result>()?;
                     write!(f, "{}", self.style.end())
                 } else {
@@ -480,32 +624,67 @@ impl Highlighter for MyHelper {
         }
         Lines {
             iter: hint.split('\n'),
-            style: Style::new().fg_color(Some(AnsiColor::BrightBlack.into())),
+            ps2: self.prompt.ps2.as_str(),
+            style: if self.error_hint { self.theme.unknown } else { self.theme.hint },
+            bold: self.hint_bold.clone(),
             _marker: PhantomData,
         }
     }
 }
 
 #[inline]
-fn run_shell(mut init_cmds: Vec<String>) -> Result<(), ExecErr> {
+fn run_shell(
+    config: &Config,
+    mut init_cmds: Vec<String>,
+    on_error: bool,
+) -> Result<(), ExecErr> {
     use core::sync::atomic::Ordering;
-    let mut rl = Editor::<MyHelper, DefaultHistory>::new(MyHelper::new())?;
+    let theme = Theme::load(&config.theme)?;
+    let mut rl = Editor::<MyHelper, DefaultHistory>::new(MyHelper::new(
+        &config.prompt,
+        theme,
+        &config.indent,
+    ))?;
+    rl.helper_mut().on_error = on_error;
+    let (ps1, terminate_n) = (config.prompt.ps1.as_str(), config.terminate_n);
+    rl.set_max_history_size(config.history.size)?;
+    rl.set_history_ignore_dups(true)?;
+    rl.set_history_ignore_space(true);
+    // a paste is one insert, the Enter handler and the validator never see its lines
+    rl.enable_bracketed_paste(true);
+    // a tab is dedented as one char
+    rl.set_indent_size(if config.indent == "\t" { 1 } else { config.indent.len() });
+    let history = config
+        .history
+        .file
+        .as_deref()
+        .and_then(|file| load_history(&mut rl, file));
     init_cmds.reverse();
+    let mut bindings = Vec::new();
+    let sequences = config.keys.sequences().expect("validated by `Config::load`");
+    for (name, seq, action) in sequences {
+        if let Some(handler) = key_handler(action, &config.indent) {
+            rl.bind_sequence(Event::KeySeq(seq), handler);
+            bindings.push((name.to_owned(), action.to_string()));
+        }
+    }
+    *keys::BINDINGS.lock().unwrap() = bindings;
+    *keys::EDIT_MODE.lock().unwrap() = config.edit_mode;
     rl.bind_sequence(
-        KeyEvent(KeyCode::Tab, Modifiers::NONE),
-        EventHandler::Simple(Cmd::Indent(Movement::ForwardChar(4))),
-    );
-    rl.bind_sequence(
-        KeyEvent(KeyCode::BackTab, Modifiers::NONE),
-        EventHandler::Simple(Cmd::Dedent(Movement::BackwardChar(4))),
-    );
-    rl.bind_sequence(
-        KeyEvent(KeyCode::Char('s'), Modifiers::CTRL),
-        EventHandler::Simple(Cmd::Newline),
+        KeyEvent(KeyCode::Enter, Modifiers::NONE),
+        EventHandler::Conditional(Box::new(EnterHandler(
+            config.indent.clone(),
+            config.prompt.ps1.clone(),
+        ))),
     );
     let mut terminate_count: u8 = 0;
+    // the entry picked by the history search, edited at the next prompt
+    let mut initial: Option<String> = None;
     Python::with_gil(|py| {
         py::init(py)?;
+        py::catch_sigint(py)?;
+        display::install(py)?;
+        rl.helper_mut().modules.refresh(py);
         loop {
             if py::EXIT.load(Ordering::Relaxed) {
                 return Err(ExecErr::Exit(py::EXIT_CODE.load(Ordering::Relaxed)));
@@ -515,24 +694,63 @@ fn run_shell(mut init_cmds: Vec<String>) -> Result<(), ExecErr> {
                 rl.clear_screen()?;
                 rl.helper_mut().on_error = false;
             }
-            rl.helper_mut().on_error = false;
-            let input = if let Some(input) = init_cmds.pop() {
+            // switched by `%mode`
+            rl.set_edit_mode(match *keys::EDIT_MODE.lock().unwrap() {
+                config::EditMode::Emacs => rustyline::EditMode::Emacs,
+                config::EditMode::Vi => rustyline::EditMode::Vi,
+            });
+            if let Some(name) = py::THEME.lock().unwrap().take() {
+                match Theme::load(&name) {
+                    Ok(theme) => rl.helper_mut().theme = theme,
+                    Err(e) => println!("{}", e),
+                }
+            }
+            let (input, is_init) = if let Some(input) = init_cmds.pop() {
                 let helper = rl.helper_mut();
                 helper.update_after_edit(&input, 0, true);
-                DisplayOnce::print(helper.highlight_prompt(PROMPT1, true))?;
+                DisplayOnce::print(helper.highlight_prompt(ps1, true))?;
                 DisplayOnce::print(helper.highlight(&input, 0))?;
                 print!("\n");
-                input
+                (input, true)
             } else {
-                match rl.readline(PROMPT1) {
-                    Ok(input) => input,
+                let input = match initial.take() {
+                    Some(initial) => {
+                        rl.helper_mut().update_after_edit(&initial, initial.len(), true);
+                        rl.readline_with_initial(ps1, (&initial, ""))
+                    }
+                    None => rl.readline(ps1),
+                };
+                match input {
+                    Ok(input) => (input, false),
                     Err(ReadlineError::Interrupted | ReadlineError::Eof) => {
-                        if terminate_count >= TERMINATE_N {
+                        // a python key binding failed, back to the prompt with the buffer
+                        if let Some((e, line)) = keys::ERROR.lock().unwrap().take() {
+                            let helper = rl.helper_mut();
+                            traceback::print(
+                                py,
+                                &e,
+                                &helper.theme,
+                                config.full_traceback,
+                            );
+                            helper.on_error = true;
+                            initial = Some(line);
+                            continue;
+                        }
+                        // Ctrl-R, back to the prompt with the picked entry or the query
+                        if let Some(query) = fuzzy::QUERY.lock().unwrap().take() {
+                            let entries = rl.history().iter().rev().cloned();
+                            let theme = &rl.helper().theme;
+                            initial = Some(
+                                fuzzy::pick(&query, entries, theme)?.unwrap_or(query),
+                            );
+                            continue;
+                        }
+                        if terminate_count >= terminate_n {
                             return Ok(());
                         }
                         println!(
                             "Need {} interrupt to exit..",
-                            TERMINATE_N - terminate_count
+                            terminate_n - terminate_count
                         );
                         terminate_count += 1;
                         continue;
@@ -544,33 +762,142 @@ fn run_shell(mut init_cmds: Vec<String>) -> Result<(), ExecErr> {
                 }
             };
             terminate_count = 0;
+            rl.helper_mut().on_error = false;
+            // a session pasted with its prompts runs as one cell without them
+            let input = match paste::strip_prompts(&input, ps1, &config.prompt.ps2) {
+                Some(cell) => {
+                    rl.helper_mut().update_after_edit(&cell, cell.len(), true);
+                    cell
+                }
+                None => input,
+            };
             if match rl.helper().parsed.syntax() {
                 Mod::Module(module) => !module.body.is_empty(),
                 _ => true,
             } {
-                if let Err(e) = py.run_bound(&input, None, None) {
-                    println!("{}", e);
-                    rl.helper_mut().on_error = true;
+                let filename = format!("<cell-{}>", display::next_count());
+                let (parsed, history) = (&rl.helper().parsed, rl.history());
+                if let Err(e) = magic::run(py, &input, &filename, parsed, history) {
+                    let helper = rl.helper_mut();
+                    traceback::print(py, &e, &helper.theme, config.full_traceback);
+                    helper.on_error = true;
+                }
+            }
+            // init commands are not typed by user, keep them out of the history file
+            if !is_init {
+                rl.add_history_entry(input)?;
+                if let Some(history) = history {
+                    if let Err(e) = rl.append_history(history) {
+                        println!("{}: {}", history.display(), e);
+                    }
                 }
             }
-            rl.add_history_entry(input)?;
         }
     })
 }
 
+/// Load the history `file` into `rl`, the shell runs without persistent history
+/// if its dir can't be created, e.g. under a read-only home
+fn load_history<'f, H: Helper>(
+    rl: &mut Editor<H, DefaultHistory>,
+    file: &'f Path,
+) -> Option<&'f Path> {
+    if let Err(e) = file.parent().map_or(Ok(()), std::fs::create_dir_all) {
+        println!("{}: {}", file.display(), e);
+        return None;
+    }
+    match rl.load_history(file) {
+        Err(ReadlineError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => println!("{}: {}", file.display(), e),
+        Ok(()) => {}
+    }
+    Some(file)
+}
+
+/// `None` keeps the binding of rustyline
+fn key_handler(action: &KeyAction, unit: &str) -> Option<EventHandler> {
+    // rustyline indents with spaces, so a tab is inserted at the cursor
+    let indent = if unit == "\t" {
+        Cmd::Insert(1, unit.to_owned())
+    } else {
+        Cmd::Indent(Movement::ForwardChar(unit.len()))
+    };
+    Some(match action {
+        KeyAction::Default => return None,
+        KeyAction::CompleteOrIndent => {
+            EventHandler::Conditional(Box::new(TabHandler(indent)))
+        }
+        KeyAction::Complete => Cmd::Complete.into(),
+        KeyAction::Indent => indent.into(),
+        KeyAction::Dedent => Cmd::Dedent(Movement::BackwardChar(unit.len())).into(),
+        KeyAction::Newline => Cmd::Newline.into(),
+        KeyAction::AcceptLine => Cmd::AcceptLine.into(),
+        KeyAction::HistorySearch => {
+            EventHandler::Conditional(Box::new(fuzzy::SearchHandler))
+        }
+        KeyAction::Python(name) => {
+            EventHandler::Conditional(Box::new(keys::PythonHandler(name.clone())))
+        }
+    })
+}
+
+/// Write `<dir>/<module>.pyi` for each of the registered `modules` and the
+/// exported ones, or `<dir>/<module>/__init__.pyi` if it has submodules
+fn write_stubs(dir: &Path, modules: &[py::Module]) -> Result<(), ExecErr> {
+    let names = py::module_names(modules);
+    Python::with_gil(|py| {
+        for &name in &names {
+            let mut hints: Vec<_> = modules
+                .iter()
+                .filter(|module| module.name == name)
+                .flat_map(|module| (module.hints)())
+                .collect();
+            hints.extend(export::hints(name));
+            let path = dir.join(name.replace('.', "/"));
+            let is_package = names.iter().any(|other| {
+                other.strip_prefix(name).is_some_and(|rest| rest.starts_with('.'))
+            });
+            let path = if is_package {
+                path.join("__init__.pyi")
+            } else {
+                path.with_extension("pyi")
+            };
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let stub = stubgen::generate(&PyModule::import_bound(py, name)?, &hints)?;
+            std::fs::write(&path, stub)?;
+            println!("{}", path.display());
+        }
+        Ok(())
+    })
+}
+
 #[inline]
 fn run_module(py_args: &Vec<String>) -> Result<(), ExecErr> {
     Python::with_gil(|py| {
         py::import_args(py, py_args)?;
         let module = py_args.first().unwrap();
         let runpy = PyModule::import_bound(py, "runpy")?;
-        match runpy.call_method(
-            "run_module",
-            (module,),
-            Some(
-                &[("run_name", "__main__"), ("alter_sys", "true")].into_py_dict_bound(py),
-            ),
-        ) {
+        // like `runpy._run_module_as_main` of `python -m`, the code runs in the
+        // globals of `__main__` itself, which `-i` inspects even if it raised
+        let (_, spec, code) = runpy
+            .call_method1("_get_module_details", (module,))?
+            .extract::<(Bound<PyAny>, Bound<PyAny>, Bound<PyAny>)>()?;
+        let origin = spec.getattr("origin")?;
+        let sys = PyModule::import_bound(py, "sys")?;
+        sys.getattr("argv")?.set_item(0, &origin)?;
+        let globals =
+            sys.getattr("modules")?.get_item("__main__")?.getattr("__dict__")?;
+        globals.set_item("__name__", "__main__")?;
+        globals.set_item("__file__", origin)?;
+        globals.set_item("__cached__", spec.getattr("cached")?)?;
+        globals.set_item("__doc__", py.None())?;
+        globals.set_item("__loader__", spec.getattr("loader")?)?;
+        globals.set_item("__package__", spec.getattr("parent")?)?;
+        globals.set_item("__spec__", &spec)?;
+        let builtins = PyModule::import_bound(py, "builtins")?;
+        match builtins.call_method1("exec", (code, globals)) {
             Ok(_) => Ok(()),
             Err(e) => {
                 if "SystemExit: 0" == &(e.to_string()) {
@@ -588,12 +915,13 @@ fn run_command(cmd: &str, py_args: &Vec<String>) -> Result<(), ExecErr> {
     Python::with_gil(|py| {
         py::import_args(py, py_args)?;
         py::init(py)?;
-        py.run_bound(cmd, None, None).map_err(Into::into)
+        py::cache_source(py, "<string>", cmd)?;
+        py::exec(py, cmd, "<string>", 1).map_err(Into::into)
     })
 }
 
 #[inline]
-fn exec_file(py_args: &Vec<String>) -> Result<(), ExecErr> {
+fn exec_file(py_args: &Vec<String>, prompts: &config::Prompt) -> Result<(), ExecErr> {
     Python::with_gil(|py| {
         let compile_command =
             PyModule::import_bound(py, "codeop")?.getattr("compile_command")?;
@@ -603,16 +931,16 @@ fn exec_file(py_args: &Vec<String>) -> Result<(), ExecErr> {
         let mut ping_pong_line_buffer = [String::new(), String::new()];
         let mut ping_pong_idx = true;
         let mut read_res = reader.read_line(&mut ping_pong_line_buffer[0]);
-        let mut prompt = PROMPT1;
+        let mut prompt = prompts.ps1.as_str();
         let mut code = String::new();
+        // line of `file_path` where `code` starts
+        let mut line = 1;
         py::import_args(py, py_args)?;
         py::init(py)?;
         loop {
             let (this_idx, next_idx) = if read_res? == 0 {
                 if !code.is_empty() {
-                    if let Err(e) = py.run_bound(&code, None, None) {
-                        println!("{}", e);
-                    }
+                    py::exec(py, &code, file_path, line)?;
                 }
                 return Ok(());
             } else {
@@ -643,10 +971,11 @@ fn exec_file(py_args: &Vec<String>) -> Result<(), ExecErr> {
                 }
             };
             if let Ok(true) = py::is_incomplete_code(&compile_command, code_check_str) {
-                prompt = PROMPT2;
+                prompt = prompts.ps2.as_str();
             } else {
-                prompt = PROMPT1;
-                py.run_bound(&code, None, None)?;
+                prompt = prompts.ps1.as_str();
+                py::exec(py, &code, file_path, line)?;
+                line += code.matches('\n').count();
                 code.clear();
             }
         }
@@ -663,7 +992,20 @@ fn quiet_exec_file(py_args: &Vec<String>) -> Result<(), ExecErr> {
         py::import_args(py, py_args)?;
         // TODO:
         py::init(py)?;
-        py.run_bound(buf.as_str(), None, None)?;
+        py::exec(py, &buf, file_path, 1)?;
+        Ok(())
+    })
+}
+
+#[inline]
+fn run_stdin(py_args: &Vec<String>) -> Result<(), ExecErr> {
+    Python::with_gil(|py| {
+        let mut buf = String::new();
+        std::io::stdin().read_to_string(&mut buf)?;
+        py::import_args(py, py_args)?;
+        py::init(py)?;
+        py::cache_source(py, "<stdin>", &buf)?;
+        py::exec(py, &buf, "<stdin>", 1)?;
         Ok(())
     })
 }
@@ -672,25 +1014,77 @@ mod test {
     #[test]
     fn test_exec_file() {
         use super::*;
-        use py::foo;
-        pyo3::append_to_inittab!(foo);
         pyo3::prepare_freethreaded_python();
-        exec_file(&vec!["tests/test1.py".into()]).expect("msg");
+        // `foo` of the command
+        let modules = [py::Module::new("foo", |_| Ok(()), Vec::new)];
+        Python::with_gil(|py| py::register(py, &modules)).expect("msg");
+        exec_file(&vec!["tests/test1.py".into()], &config::Prompt::default())
+            .expect("msg");
     }
     #[test]
     fn test_shell() {
         use super::*;
-        use py::foo;
-        pyo3::append_to_inittab!(foo);
         pyo3::prepare_freethreaded_python();
-        run_shell(vec![]).expect("msg");
+        Python::with_gil(|py| py::register(py, &[])).expect("msg");
+        run_shell(&Config::default(), vec![], false).expect("msg");
+    }
+    #[test]
+    fn test_history() {
+        use super::*;
+        use rustyline::history::{History, SearchDirection};
+        let dir =
+            std::env::temp_dir().join(format!("pyapp_history_{}", std::process::id()));
+        let file = dir.join("data/history");
+        let editor = || {
+            let helper =
+                MyHelper::new(&Config::default().prompt, Theme::default(), "    ");
+            Editor::<MyHelper, DefaultHistory>::new(helper).expect("msg")
+        };
+        let cell = "for i in range(4):\n    print('\\n', i)";
+        let mut rl = editor();
+        assert_eq!(load_history(&mut rl, &file), Some(file.as_path()));
+        rl.add_history_entry(cell).expect("msg");
+        rl.append_history(&file).expect("msg");
+        let mut rl = editor();
+        load_history(&mut rl, &file).expect("msg");
+        assert_eq!(
+            rl.history()
+                .get(0, SearchDirection::Forward)
+                .expect("msg")
+                .map(|sr| sr.entry),
+            Some(cell.into())
+        );
+        // a file where the dir should be
+        assert_eq!(load_history(&mut rl, &file.join("history")), None);
+        std::fs::remove_dir_all(&dir).expect("msg");
+    }
+    #[test]
+    fn test_write_stubs() {
+        use super::*;
+        use pyo3::prelude::*;
+        #[pyapp::export(module = "stubs.sub")]
+        #[pyfunction]
+        fn answer() -> i64 {
+            42
+        }
+        pyo3::prepare_freethreaded_python();
+        let modules = [py::Module::new("stubs", |_| Ok(()), Vec::new)];
+        Python::with_gil(|py| py::register(py, &modules)).expect("msg");
+        let dir =
+            std::env::temp_dir().join(format!("pyapp_stubs_{}", std::process::id()));
+        write_stubs(&dir, &modules).expect("msg");
+        let stub = std::fs::read_to_string(dir.join("stubs/sub.pyi")).expect("msg");
+        assert!(stub.contains("def answer() -> int: ...\n"));
+        // a module with submodules is a package
+        assert!(dir.join("stubs/__init__.pyi").is_file());
+        assert!(!dir.join("stubs.pyi").exists());
+        std::fs::remove_dir_all(&dir).expect("msg");
     }
     #[test]
     fn test_cmd() {
         use super::*;
-        use py::foo;
-        pyo3::append_to_inittab!(foo);
         pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| py::register(py, &[])).expect("msg");
         run_command(
             "import sys;print(sys.argv)".into(),
             &vec!["-c".into(), "11".into(), "22".into()],
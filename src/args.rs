@@ -1,5 +1,5 @@
 use core::fmt;
-use std::ffi::OsString;
+use std::{ffi::OsString, path::PathBuf};
 use thiserror::Error;
 
 #[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
@@ -13,6 +13,13 @@ pub(crate) enum Mode {
     ExecModule(Vec<String>),
     /// Execute a command
     Command(String, Vec<String>),
+    /// Execute the program read from stdin with arguments
+    /// (interactive shell if stdin is a tty)
+    Stdin(Vec<String>),
+    /// Write `.pyi` stubs of the embedded Rust modules into the directory
+    StubGen(PathBuf),
+    /// Run as a jupyter kernel with the connection file
+    Kernel(PathBuf),
 }
 
 #[derive(Error, Debug)]
@@ -22,6 +29,8 @@ pub(crate) enum ArgsError {
     UnknowShort(char),
     #[error("Unknow Flag '--{0}'")]
     UnknowLong(String),
+    #[error("Unknow Argument '{0}' of kernel")]
+    UnknowKernelArg(String),
     #[error("ExpectValue {0}")]
     ExpectValue(Arg),
     #[error("OsString {0:?}")]
@@ -34,6 +43,9 @@ pub(crate) struct Flag {
     /// execute in quiet mode (effect in file mode)
     // -q
     pub(crate) quiet: bool,
+    /// inspect interactively after running file, module or command
+    // -i
+    pub(crate) inspect: bool,
     /// [PYTHON] isolate Python from the user's environment (implies -E and -s)
     // -I
     pub(crate) isolate: bool,
@@ -43,6 +55,15 @@ pub(crate) struct Flag {
     /// [PYTHON] ignore PYTHON* environment variables (such as PYTHONPATH)
     // -E
     pub(crate) ignore_env: bool,
+    /// history file of interactive shell, also PYAPP_HISTORY
+    // --history <file>
+    pub(crate) history: Option<PathBuf>,
+    /// config file of interactive shell
+    // --config <file>
+    pub(crate) config: Option<PathBuf>,
+    /// color theme of interactive shell, built-in name or theme file
+    // --theme <name|file>
+    pub(crate) theme: Option<String>,
 }
 #[derive(Debug, Default)]
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
@@ -58,6 +79,16 @@ pub(crate) enum Arg {
     Module,
     // -c
     Command,
+    // --history
+    History,
+    // --config
+    Config,
+    // --theme
+    Theme,
+    // --stubgen
+    StubGen,
+    // kernel --f
+    ConnectionFile,
 }
 
 impl fmt::Display for Arg {
@@ -66,6 +97,11 @@ impl fmt::Display for Arg {
         match self {
             Arg::Module => f.write_str("-m"),
             Arg::Command => f.write_str("-c"),
+            Arg::History => f.write_str("--history"),
+            Arg::Config => f.write_str("--config"),
+            Arg::Theme => f.write_str("--theme"),
+            Arg::StubGen => f.write_str("--stubgen"),
+            Arg::ConnectionFile => f.write_str("kernel --f"),
         }
     }
 }
@@ -83,20 +119,32 @@ impl Args {
             println!("An example application\n");
             0
         };
-        println!("Usage: {} [option] ... [-c cmd | -m mod | file | -] [arg] ...
+        println!("Usage: {0} [option] ... [-c cmd | -m mod | file | -] [arg] ...
+       {0} [option] ... kernel --f <connection.json>
 
 Arguments:
     [file]    [PYTHON] program read from script file
     [arg] ... [PYTHON] arguments passed to program in sys.argv[1:]
     [ - ]     [PYTHON] program read from stdin (default; interactive mode if a tty)
+    kernel    run as a jupyter kernel, -f or --f[=]<file> is the connection file
 
 Options:
     -q, --quiet    execute in quiet mode (effect in file mode)
+    -i             inspect interactively after running file, module or command
     -I             [PYTHON] isolate Python from the user's environment (implies -E and -s)
     -s             [PYTHON] don't add user site directory to sys.path; also PYTHONNOUSERSITE
     -E             [PYTHON] ignore PYTHON* environment variables (such as PYTHONPATH)
     -m <mod>       [PYTHON] run library module as a script (terminates option list)
     -c <cmd>       [PYTHON] program passed in as string (terminates option list)
+    --history <file>
+                   history file of interactive shell, also PYAPP_HISTORY (empty to disable)
+    --config <file>
+                   config file of interactive shell [default: ~/.config/pyapp/config.toml]
+    --theme <name|file>
+                   color theme of interactive shell: dark, light, high-contrast, monochrome
+                   or a theme file [default: dark]
+    --stubgen <dir>
+                   write .pyi stubs of the embedded Rust modules into <dir>, then exit
     -h, --help     Print help
     -V, --version  Print version", env!("CARGO_PKG_NAME"));
         std::process::exit(code)
@@ -115,6 +163,22 @@ Options:
                     *last_arg = None;
                     Ok(())
                 }
+                "history" => {
+                    *last_arg = Some(Arg::History);
+                    Ok(())
+                }
+                "config" => {
+                    *last_arg = Some(Arg::Config);
+                    Ok(())
+                }
+                "theme" => {
+                    *last_arg = Some(Arg::Theme);
+                    Ok(())
+                }
+                "stubgen" => {
+                    *last_arg = Some(Arg::StubGen);
+                    Ok(())
+                }
                 "help" => {
                     Self::help(None);
                 }
@@ -148,6 +212,11 @@ Options:
                     *last_arg = None;
                     Ok(())
                 }
+                'i' => {
+                    flag.inspect = true;
+                    *last_arg = None;
+                    Ok(())
+                }
                 'I' => {
                     flag.isolate = true;
                     *last_arg = None;
@@ -173,6 +242,30 @@ Options:
             }
         }
     }
+    /// The connection file after `kernel`, as `-f <file>`, `--f <file>` or `--f=<file>`
+    /// like `ipykernel_launcher`
+    fn parse_kernel<T: Into<OsString>>(
+        iter: &mut impl Iterator<Item = T>,
+    ) -> Result<PathBuf, ArgsError> {
+        let mut file = None;
+        let mut expect_file = false;
+        for arg in iter {
+            let arg = Into::<OsString>::into(arg)
+                .into_string()
+                .map_err(ArgsError::OsString)?;
+            if expect_file {
+                file = Some(arg.into());
+                expect_file = false;
+            } else if arg == "-f" || arg == "--f" {
+                expect_file = true;
+            } else if let Some(f) = arg.strip_prefix("--f=").or(arg.strip_prefix("-f=")) {
+                file = Some(f.into());
+            } else {
+                return Err(ArgsError::UnknowKernelArg(arg));
+            }
+        }
+        file.ok_or(ArgsError::ExpectValue(Arg::ConnectionFile))
+    }
     pub(crate) fn parse() -> Self {
         Self::parse_from(std::env::args_os()).unwrap_or_else(|e| Self::help(Some(e)))
     }
@@ -194,10 +287,8 @@ Options:
             let mut chars: std::str::Chars = arg_str.chars();
             match chars.next() {
                 None => continue,
-                Some('-') => match chars.next() {
-                    None => {
-                        return Err(ArgsError::UnknowShort('\0'));
-                    }
+                Some('-') if arg_str != "-" => match chars.next() {
+                    None => unreachable!(),
                     Some('-') => {
                         Self::match_long(&arg_str[2..], &mut out.flag, &mut last_arg)?;
                     }
@@ -209,8 +300,16 @@ Options:
                     }
                 },
                 _ => match last_arg {
+                    None if arg_str == "kernel" => {
+                        out.mode = Mode::Kernel(Self::parse_kernel(&mut iter)?);
+                        break;
+                    }
                     None => {
-                        out.mode = Mode::ExecFile(vec![arg_str.into()]);
+                        out.mode = if arg_str == "-" {
+                            Mode::Stdin(vec![arg_str.into()])
+                        } else {
+                            Mode::ExecFile(vec![arg_str.into()])
+                        };
                         break;
                     }
                     Some(Arg::Module) => {
@@ -221,10 +320,27 @@ Options:
                         out.mode = Mode::Command(arg_str.into(), vec!["-c".to_owned()]);
                         break;
                     }
+                    Some(Arg::History) => {
+                        out.flag.history = Some(arg_str.into());
+                        last_arg = None;
+                    }
+                    Some(Arg::Config) => {
+                        out.flag.config = Some(arg_str.into());
+                        last_arg = None;
+                    }
+                    Some(Arg::Theme) => {
+                        out.flag.theme = Some(arg_str.into());
+                        last_arg = None;
+                    }
+                    Some(Arg::StubGen) => {
+                        out.mode = Mode::StubGen(arg_str.into());
+                        break;
+                    }
                 },
             }
         }
         match &mut out.mode {
+            Mode::StubGen(_) | Mode::Kernel(_) => Ok(out),
             Mode::InteractiveShell => {
                 if let Some(last) = last_arg {
                     Err(ArgsError::ExpectValue(last))
@@ -232,7 +348,10 @@ Options:
                     Ok(out)
                 }
             }
-            Mode::ExecFile(args) | Mode::ExecModule(args) | Mode::Command(_, args) => {
+            Mode::ExecFile(args)
+            | Mode::ExecModule(args)
+            | Mode::Command(_, args)
+            | Mode::Stdin(args) => {
                 if let Err(e) = iter.try_for_each(|arg| {
                     Into::<OsString>::into(arg).into_string().map(|s| args.push(s))
                 }) {
@@ -299,6 +418,96 @@ mod test {
                 }
             })
         );
+        assert_eq!(
+            Args::parse_from(&["-iqm", "mod", "arg1"]),
+            Ok(Args {
+                mode: Mode::ExecModule(vec!["mod".into(), "arg1".into()]),
+                flag: {
+                    let mut f = Flag::default();
+                    f.quiet = true;
+                    f.inspect = true;
+                    f
+                }
+            })
+        );
+        assert_eq!(
+            Args::parse_from(&["-q", "-", "arg1"]),
+            Ok(Args {
+                mode: Mode::Stdin(vec!["-".into(), "arg1".into()]),
+                flag: {
+                    let mut f = Flag::default();
+                    f.quiet = true;
+                    f
+                }
+            })
+        );
+        assert_eq!(
+            Args::parse_from(&["-c", "-"]),
+            Ok(Args {
+                mode: Mode::Command("-".into(), vec!["-c".into()]),
+                flag: Flag::default()
+            })
+        );
+        assert_eq!(
+            Args::parse_from(&[
+                "--history",
+                "hist",
+                "-s",
+                "--config",
+                "c.toml",
+                "--theme",
+                "light",
+                "run.py"
+            ]),
+            Ok(Args {
+                mode: Mode::ExecFile(vec!["run.py".into()]),
+                flag: {
+                    let mut f = Flag::default();
+                    f.ignore_site = true;
+                    f.history = Some("hist".into());
+                    f.config = Some("c.toml".into());
+                    f.theme = Some("light".into());
+                    f
+                }
+            })
+        );
+        assert_eq!(
+            Args::parse_from(&["-I", "--stubgen", "stubs"]),
+            Ok(Args {
+                mode: Mode::StubGen("stubs".into()),
+                flag: {
+                    let mut f = Flag::default();
+                    f.isolate = true;
+                    f
+                }
+            })
+        );
+        assert_eq!(
+            Args::parse_from(&["-s", "kernel", "--f=/tmp/kernel-1.json"]),
+            Ok(Args {
+                mode: Mode::Kernel("/tmp/kernel-1.json".into()),
+                flag: {
+                    let mut f = Flag::default();
+                    f.ignore_site = true;
+                    f
+                }
+            })
+        );
+        assert_eq!(
+            Args::parse_from(&["kernel", "-f", "k.json"]),
+            Ok(Args {
+                mode: Mode::Kernel("k.json".into()),
+                flag: Flag::default()
+            })
+        );
+        assert_eq!(
+            Args::parse_from(&["kernel"]),
+            Err(ArgsError::ExpectValue(Arg::ConnectionFile))
+        );
+        assert_eq!(
+            Args::parse_from(&["--history"]),
+            Err(ArgsError::ExpectValue(Arg::History))
+        );
         assert_eq!(Args::parse_from(&["-c"]), Err(ArgsError::ExpectValue(Arg::Command)));
         assert_eq!(Args::parse_from(&["-m"]), Err(ArgsError::ExpectValue(Arg::Module)));
         assert_eq!(
@@ -0,0 +1,199 @@
+use crate::{
+    app, args,
+    config::{Config, EditMode, Key, KeyAction},
+    py::{Module, ModuleInit},
+    stubgen::FnHint,
+    ExitCode,
+};
+
+/// Builder of the shell, for Rust programs that embed it with their own
+/// modules. Its settings are the defaults, which the config file and the
+/// flags of the user still override.
+///
+/// ```no_run
+/// use pyapp::ShellBuilder;
+/// use pyo3::prelude::*;
+///
+/// #[pymodule]
+/// fn host(m: &Bound<'_, PyModule>) -> PyResult<()> {
+///     m.add("version", "1.0")
+/// }
+///
+/// fn main() -> pyapp::ExitCode {
+///     ShellBuilder::new()
+///         .module("host", host)
+///         .prompt("host > ", " ...> ")
+///         .init_cmds(["import host"])
+///         .run_cli()
+/// }
+/// ```
+pub struct ShellBuilder {
+    modules: Vec<Module>,
+    config: Config,
+}
+
+impl Default for ShellBuilder {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShellBuilder {
+    #[inline]
+    pub fn new() -> Self {
+        Self { modules: Vec::new(), config: Config::default() }
+    }
+    /// Register `init`, such as a `#[pymodule]` function, as the module `name`
+    #[inline]
+    pub fn module(self, name: &'static str, init: ModuleInit) -> Self {
+        self.module_with_hints(name, init, Vec::new)
+    }
+    /// Same as [`Self::module`], with the type hints used by `--stubgen`
+    #[inline]
+    pub fn module_with_hints(
+        mut self,
+        name: &'static str,
+        init: ModuleInit,
+        hints: fn() -> Vec<FnHint>,
+    ) -> Self {
+        self.modules.retain(|module| module.name != name);
+        self.modules.push(Module::new(name, init, hints));
+        self
+    }
+    /// Plain primary and continuation prompts
+    #[inline]
+    pub fn prompt(mut self, ps1: &str, ps2: &str) -> Self {
+        let prompt = &mut self.config.prompt;
+        prompt.ps1 = ps1.to_owned();
+        prompt.ps1_ok = ps1.to_owned();
+        prompt.ps1_err = ps1.to_owned();
+        prompt.ps2 = ps2.to_owned();
+        prompt.ps2_ok = ps2.to_owned();
+        self
+    }
+    /// Styled prompts after success and error, and the styled continuation
+    /// prompt, each as wide as the plain one of [`Self::prompt`]
+    #[inline]
+    pub fn styled_prompt(mut self, ps1_ok: &str, ps1_err: &str, ps2_ok: &str) -> Self {
+        let prompt = &mut self.config.prompt;
+        prompt.ps1_ok = ps1_ok.to_owned();
+        prompt.ps1_err = ps1_err.to_owned();
+        prompt.ps2_ok = ps2_ok.to_owned();
+        self
+    }
+    /// Built-in theme name or theme file
+    #[inline]
+    pub fn theme(mut self, theme: &str) -> Self {
+        self.config.theme = theme.to_owned();
+        self
+    }
+    /// Commands shown and executed when the shell starts
+    #[inline]
+    pub fn init_cmds<I, S>(mut self, cmds: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.init_cmds = cmds.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Indent unit of the editor, spaces or `"\t"`
+    #[inline]
+    pub fn indent(mut self, unit: &str) -> Self {
+        self.config.indent = unit.to_owned();
+        self
+    }
+    /// Key map of the editor, switched at runtime by `%mode`
+    #[inline]
+    pub fn edit_mode(mut self, mode: EditMode) -> Self {
+        self.config.edit_mode = mode;
+        self
+    }
+    #[inline]
+    pub fn bind(mut self, key: Key, action: KeyAction) -> Self {
+        self.config.keys.bind(key, action);
+        self
+    }
+    /// Run as the `pyapp` command, with the arguments of the process
+    #[inline]
+    pub fn run_cli(self) -> ExitCode {
+        self.run(args::Args::parse())
+    }
+    /// Start the interactive shell
+    #[inline]
+    pub fn run_shell(self) -> ExitCode {
+        self.run(args::Args::default())
+    }
+    /// Execute the file at `path`, with `args` in `sys.argv`
+    #[inline]
+    pub fn exec_file(self, path: &str, args: &[&str]) -> ExitCode {
+        let py_args = [path].iter().chain(args).map(|&arg| arg.to_owned()).collect();
+        self.run(args::Args {
+            mode: args::Mode::ExecFile(py_args),
+            ..Default::default()
+        })
+    }
+    /// Execute `cmd`, with `args` in `sys.argv`
+    #[inline]
+    pub fn run_command(self, cmd: &str, args: &[&str]) -> ExitCode {
+        let py_args = ["-c"].iter().chain(args).map(|&arg| arg.to_owned()).collect();
+        let mode = args::Mode::Command(cmd.to_owned(), py_args);
+        self.run(args::Args { mode, ..Default::default() })
+    }
+    #[inline]
+    fn run(self, args: args::Args) -> ExitCode {
+        if let Err(e) = self.config.validate() {
+            return ExitCode::invalid_builder(e);
+        }
+        app::run(args, &self.modules, self.config)
+    }
+}
+
+mod test {
+    #[test]
+    fn test_config() {
+        use super::*;
+        use crate::args::Flag;
+        let builder = ShellBuilder::new()
+            .module("host", |_| Ok(()))
+            .prompt("host > ", " ...> ")
+            .theme("light")
+            .indent("  ")
+            .bind(Key::CtrlS, KeyAction::Newline);
+        assert_eq!(builder.modules.len(), 1);
+        builder.config.validate().expect("msg");
+        let file = std::env::temp_dir()
+            .join(format!("pyapp_builder_{}.toml", std::process::id()));
+        std::fs::write(&file, "indent = \"\\t\"\ntheme = \"dark\"").expect("msg");
+        let flag = Flag {
+            config: Some(file.clone()),
+            theme: Some("monochrome".to_owned()),
+            ..Default::default()
+        };
+        // builder < file < flags
+        let config = Config::load(&flag, builder.config).expect("msg");
+        std::fs::remove_file(&file).expect("msg");
+        assert_eq!(config.prompt.ps1, "host > ");
+        assert_eq!(config.keys.get("ctrl-s"), Some(&KeyAction::Newline));
+        assert_eq!(config.indent, "\t");
+        assert_eq!(config.theme, "monochrome");
+        let invalid = ShellBuilder::new().indent("x").run_command("pass", &[]);
+        assert!(!invalid.is_success());
+    }
+    #[test]
+    fn test_run_command() {
+        use super::*;
+        use pyo3::prelude::*;
+        use std::process::Termination;
+        fn host(m: &Bound<'_, PyModule>) -> PyResult<()> {
+            m.add("version", "1.0")
+        }
+        let exit = ShellBuilder::new()
+            .module("host", host)
+            .run_command("import host; exit(3)", &[]);
+        assert!(exit.report() == std::process::ExitCode::from(3));
+        let exit = ShellBuilder::new().run_command("assert False", &[]);
+        assert!(exit.report() == std::process::ExitCode::FAILURE);
+    }
+}
@@ -0,0 +1,569 @@
+use crate::py;
+use pyo3::{
+    prelude::*,
+    types::{PyDict, PyList},
+};
+use ruff_python_ast::{Expr, Mod, Stmt};
+use ruff_python_parser::{Parsed, TokenKind};
+use ruff_text_size::TextRange;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How long a scan of `sys.path` stays fresh
+const MODULE_RESCAN: Duration = Duration::from_secs(30);
+
+/// What the word under the cursor should be completed against
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Context<'l> {
+    /// `__main__` globals, builtins and keywords
+    Name,
+    /// attributes of the dotted names before the cursor, e.g. `foo.` → `["foo"]`
+    Attr(Vec<&'l str>),
+    /// modules in the package, e.g. `import os.` → `["os"]`, top-level if empty
+    Module(Vec<&'l str>),
+    /// submodules and members of the module, e.g. `from os import ` → `["os"]`
+    Member(Vec<&'l str>),
+}
+
+#[inline]
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Whether Tab at `pos` should complete rather than indent:
+/// after an identifier or `.`, or anywhere in an import statement
+#[inline]
+pub(crate) fn tab_completes(line: &str, pos: usize) -> bool {
+    let before = &line[..pos];
+    let current = before[before.rfind('\n').map_or(0, |i| i + 1)..].trim_start();
+    before.ends_with('.')
+        || (current.starts_with("import ") || current.starts_with("from "))
+        || {
+            let start = before
+                .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map_or(0, |i| i + 1);
+            is_identifier(&before[start..])
+        }
+}
+
+/// `a.b` → `["a", "b"]`, or `a.b.` → `["a", "b"]` if `trailing_dot`
+fn dotted<'l>(
+    tokens: &[(TokenKind, TextRange)],
+    line: &'l str,
+    trailing_dot: bool,
+) -> Option<Vec<&'l str>> {
+    let mut chain = Vec::new();
+    let mut expect_name = true;
+    for (kind, range) in tokens {
+        match (expect_name, kind) {
+            (true, TokenKind::Name) => chain.push(&line[*range]),
+            (false, TokenKind::Dot) => {}
+            _ => return None,
+        }
+        expect_name = !expect_name;
+    }
+    (expect_name == trailing_dot).then_some(chain)
+}
+
+/// The completion context of `import ...` and `from ... import ...`,
+/// `None` if the statement under the cursor is not an import
+fn import_context<'l>(
+    tokens: &[(TokenKind, TextRange)],
+    line: &'l str,
+    pos: usize,
+) -> Option<Option<(usize, Context<'l>)>> {
+    let stmt_start = tokens
+        .iter()
+        .rposition(|(kind, _)| {
+            matches!(kind, TokenKind::Newline | TokenKind::Semi | TokenKind::Colon)
+        })
+        .map_or(0, |i| i + 1);
+    let stmt = tokens[stmt_start..]
+        .iter()
+        .filter(|(kind, _)| {
+            !matches!(
+                kind,
+                TokenKind::Indent
+                    | TokenKind::Dedent
+                    | TokenKind::Comment
+                    | TokenKind::NonLogicalNewline
+            )
+        })
+        .copied()
+        .collect::<Vec<_>>();
+    let first = match stmt.first() {
+        Some((kind @ (TokenKind::Import | TokenKind::From), _)) => *kind,
+        _ => return None,
+    };
+    let (start, before) = match stmt.last() {
+        Some((TokenKind::Name, range)) if range.end().to_usize() >= pos => {
+            (range.start().to_usize(), &stmt[..stmt.len() - 1])
+        }
+        Some((TokenKind::Dot, range)) if range.end().to_usize() == pos => {
+            (pos, &stmt[..])
+        }
+        Some((_, range)) if range.end().to_usize() < pos => (pos, &stmt[..]),
+        _ => return Some(None),
+    };
+    let import_idx = before.iter().rposition(|(kind, _)| *kind == TokenKind::Import);
+    Some(match (first, import_idx) {
+        (TokenKind::From, Some(idx)) => match before.last() {
+            Some((TokenKind::Import | TokenKind::Comma | TokenKind::Lpar, _)) => {
+                dotted(&before[1..idx], line, false)
+                    .map(|chain| (start, Context::Member(chain)))
+            }
+            _ => None,
+        },
+        (TokenKind::From, None) => {
+            // `from x imp` is still typing the keyword
+            if before.len() > 1
+                && before.last().is_some_and(|(kind, _)| *kind == TokenKind::Name)
+            {
+                return None;
+            }
+            dotted(&before[1..], line, true).map(|chain| (start, Context::Module(chain)))
+        }
+        _ => {
+            let seg_start = before
+                .iter()
+                .rposition(|(kind, _)| {
+                    matches!(kind, TokenKind::Import | TokenKind::Comma)
+                })
+                .map_or(before.len(), |i| i + 1);
+            dotted(&before[seg_start..], line, true)
+                .map(|chain| (start, Context::Module(chain)))
+        }
+    })
+}
+
+/// Find the completion context from the token stream,
+/// return the start of the word to replace and its context
+pub(crate) fn context<'l>(
+    parsed: &Parsed<Mod>,
+    line: &'l str,
+    pos: usize,
+) -> Option<(usize, Context<'l>)> {
+    let tokens = parsed
+        .tokens()
+        .iter()
+        .map(|token| token.as_tuple())
+        .filter(|(_, range)| range.len().to_u32() != 0 && range.start().to_usize() < pos)
+        .collect::<Vec<_>>();
+    if let Some(import) = import_context(&tokens, line, pos) {
+        return import;
+    }
+    let mut tokens_rev = tokens.into_iter().rev();
+    let (kind, range) = tokens_rev.next()?;
+    let (start, dot) = match kind {
+        TokenKind::Dot if range.end().to_usize() == pos => (pos, true),
+        TokenKind::String | TokenKind::FStringMiddle | TokenKind::Comment => return None,
+        _ if range.end().to_usize() >= pos
+            && is_identifier(&line[range.start().to_usize()..pos]) =>
+        {
+            let start = range.start().to_usize();
+            match tokens_rev.next() {
+                Some((TokenKind::Dot, _)) => (start, true),
+                _ => (start, false),
+            }
+        }
+        _ => return None,
+    };
+    if !dot {
+        return Some((start, Context::Name));
+    }
+    // collect `a.b.c` backwards, anything but a plain name chain is not evaluated
+    let mut chain = Vec::new();
+    loop {
+        match tokens_rev.next() {
+            Some((TokenKind::Name, range)) => chain.push(&line[range]),
+            _ => return None,
+        }
+        match tokens_rev.next() {
+            Some((TokenKind::Dot, _)) => continue,
+            _ => break,
+        }
+    }
+    chain.reverse();
+    Some((start, Context::Attr(chain)))
+}
+
+/// Resolve a dotted name from `__main__` globals and builtins,
+/// only attribute lookups are performed
+pub(crate) fn resolve<'py>(py: Python<'py>, chain: &[&str]) -> Option<Bound<'py, PyAny>> {
+    let (root, attrs) = chain.split_first()?;
+    let mut obj = global(py, root)?;
+    for attr in attrs {
+        obj = obj.getattr(*attr).ok()?;
+    }
+    Some(obj)
+}
+
+/// `name` in the `__main__` globals or the builtins
+pub(crate) fn global<'py>(py: Python<'py>, name: &str) -> Option<Bound<'py, PyAny>> {
+    [main_dict(py)?, builtins_dict(py)?]
+        .iter()
+        .find_map(|dict| dict.get_item(name).ok().flatten())
+}
+
+#[inline]
+pub(crate) fn main_dict(py: Python) -> Option<Bound<PyDict>> {
+    PyModule::import_bound(py, "__main__").ok().map(|m| m.dict())
+}
+
+#[inline]
+fn builtins_dict(py: Python) -> Option<Bound<PyDict>> {
+    PyModule::import_bound(py, "builtins").ok().map(|m| m.dict())
+}
+
+/// Importable modules, `sys.path` is scanned in the background
+/// so typing an import never waits for the file system
+pub(crate) struct ModuleCache {
+    /// `sys.builtin_module_names`, and the registered modules like `foo`
+    builtin: Vec<String>,
+    /// `importlib.machinery.all_suffixes()`
+    suffixes: Arc<Vec<String>>,
+    paths: Arc<Vec<PathBuf>>,
+    scanned: Arc<Mutex<Vec<String>>>,
+    scanning: Arc<AtomicBool>,
+    last_scan: Option<Instant>,
+}
+
+impl ModuleCache {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Self {
+            builtin: Vec::new(),
+            suffixes: Arc::new(Vec::new()),
+            paths: Arc::new(Vec::new()),
+            scanned: Arc::new(Mutex::new(Vec::new())),
+            scanning: Arc::new(AtomicBool::new(false)),
+            last_scan: None,
+        }
+    }
+    /// Rescan in the background when `sys.path` changed or the last scan is stale
+    pub(crate) fn refresh(&mut self, py: Python) {
+        let sys = match PyModule::import_bound(py, "sys") {
+            Ok(sys) => sys,
+            Err(_) => return,
+        };
+        let paths: Vec<PathBuf> = sys
+            .getattr("path")
+            .and_then(|p| p.extract::<Vec<String>>())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|p| if p.is_empty() { ".".into() } else { p.into() })
+            .collect();
+        if self.last_scan.is_some_and(|t| t.elapsed() < MODULE_RESCAN)
+            && *self.paths == paths
+        {
+            return;
+        }
+        if self.last_scan.is_none() {
+            self.builtin = sys
+                .getattr("builtin_module_names")
+                .and_then(|names| names.extract())
+                .unwrap_or_default();
+            let registered = py::MODULES.lock().unwrap();
+            self.builtin.extend(registered.iter().map(|&name| name.to_owned()));
+            self.suffixes = Arc::new(
+                PyModule::import_bound(py, "importlib.machinery")
+                    .and_then(|m| m.call_method0("all_suffixes"))
+                    .and_then(|suffixes| suffixes.extract())
+                    .unwrap_or_default(),
+            );
+        }
+        if self.scanning.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.paths = Arc::new(paths);
+        self.last_scan = Some(Instant::now());
+        let (paths, suffixes, scanned, scanning) = (
+            self.paths.clone(),
+            self.suffixes.clone(),
+            self.scanned.clone(),
+            self.scanning.clone(),
+        );
+        std::thread::spawn(move || {
+            let mut names = Vec::new();
+            for path in paths.iter() {
+                scan_dir(path, &suffixes, &mut names);
+            }
+            if let Ok(mut scanned) = scanned.lock() {
+                *scanned = names;
+            }
+            scanning.store(false, Ordering::Release);
+        });
+    }
+    /// Top-level modules, or the submodules found in the package directories
+    fn modules(&self, py: Python, chain: &[&str], names: &mut Vec<String>) {
+        // registered submodules are dotted, e.g. `foo.bar`
+        let package = chain.iter().map(|name| format!("{name}.")).collect::<String>();
+        names.extend(
+            self.builtin
+                .iter()
+                .filter_map(|name| name.strip_prefix(&package))
+                .filter(|name| !name.contains('.'))
+                .map(str::to_owned),
+        );
+        if chain.is_empty() {
+            if let Ok(scanned) = self.scanned.lock() {
+                names.extend(scanned.iter().cloned());
+            }
+            return;
+        }
+        // an imported package knows its own `__path__`
+        let package_paths = sys_module(py, chain)
+            .and_then(|m| m.getattr("__path__").ok())
+            .and_then(|p| p.extract::<Vec<PathBuf>>().ok())
+            .unwrap_or_else(|| {
+                self.paths
+                    .iter()
+                    .map(|path| {
+                        chain.iter().fold(path.clone(), |dir, name| dir.join(name))
+                    })
+                    .filter(|dir| dir.join("__init__.py").is_file())
+                    .collect()
+            });
+        for dir in package_paths {
+            scan_dir(&dir, &self.suffixes, names);
+        }
+    }
+    /// Members of the module without importing it, unless it is already
+    /// imported or built in, otherwise read from its source
+    fn members(&self, py: Python, chain: &[&str], names: &mut Vec<String>) {
+        let module = sys_module(py, chain).or_else(|| match chain {
+            [name] if self.builtin.iter().any(|builtin| builtin == name) => {
+                PyModule::import_bound(py, *name).ok().map(Bound::into_any)
+            }
+            _ => None,
+        });
+        if let Some(module) = module {
+            extend_names(names, module.dir(), "");
+            return;
+        }
+        let source = self.paths.iter().find_map(|path| {
+            let base = chain.iter().fold(path.clone(), |dir, name| dir.join(name));
+            [base.join("__init__.py"), base.with_extension("py")]
+                .into_iter()
+                .find(|file| file.is_file())
+        });
+        if let Some(source) = source.and_then(|file| std::fs::read_to_string(file).ok()) {
+            source_members(&source, names);
+        }
+    }
+}
+
+/// Collect module names in `dir`: packages and files with an import suffix
+fn scan_dir(dir: &Path, suffixes: &[String], names: &mut Vec<String>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let file_name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+        if path.is_dir() {
+            if is_identifier(file_name) && path.join("__init__.py").is_file() {
+                names.push(file_name.to_owned());
+            }
+        } else if let Some(stem) = suffixes
+            .iter()
+            .filter_map(|suffix| file_name.strip_suffix(suffix.as_str()))
+            .find(|stem| is_identifier(stem) && *stem != "__init__")
+        {
+            names.push(stem.to_owned());
+        }
+    }
+}
+
+/// Top-level names bound by a python source, found by parsing only
+fn source_members(source: &str, names: &mut Vec<String>) {
+    use ruff_python_parser::{parse_unchecked, Mode};
+    let parsed = parse_unchecked(source, Mode::Module);
+    let body = match parsed.syntax() {
+        Mod::Module(module) => &module.body,
+        _ => return,
+    };
+    for stmt in body {
+        match stmt {
+            Stmt::FunctionDef(def) => names.push(def.name.to_string()),
+            Stmt::ClassDef(def) => names.push(def.name.to_string()),
+            Stmt::Assign(assign) => {
+                names.extend(assign.targets.iter().filter_map(|target| match target {
+                    Expr::Name(name) => Some(name.id.to_string()),
+                    _ => None,
+                }))
+            }
+            Stmt::AnnAssign(assign) => {
+                if let Expr::Name(name) = assign.target.as_ref() {
+                    names.push(name.id.to_string());
+                }
+            }
+            Stmt::Import(import) => {
+                names.extend(import.names.iter().map(|alias| match &alias.asname {
+                    Some(asname) => asname.to_string(),
+                    None => alias.name.split('.').next().unwrap_or_default().to_owned(),
+                }))
+            }
+            Stmt::ImportFrom(import) => names.extend(
+                import
+                    .names
+                    .iter()
+                    .map(|alias| alias.asname.as_ref().unwrap_or(&alias.name).to_string())
+                    .filter(|name| name != "*"),
+            ),
+            _ => {}
+        }
+    }
+}
+
+#[inline]
+fn sys_module<'py>(py: Python<'py>, chain: &[&str]) -> Option<Bound<'py, PyAny>> {
+    PyModule::import_bound(py, "sys")
+        .and_then(|sys| sys.getattr("modules"))
+        .and_then(|modules| modules.get_item(chain.join(".")))
+        .ok()
+}
+
+#[inline]
+fn extend_names(names: &mut Vec<String>, list: PyResult<Bound<PyList>>, prefix: &str) {
+    if let Ok(list) = list {
+        names.extend(
+            list.iter()
+                .filter_map(|name| name.extract::<String>().ok())
+                .filter(|name| name.starts_with(prefix)),
+        );
+    }
+}
+
+/// Sorted candidates starting with `prefix`,
+/// private names are hidden unless `prefix` starts with `_`
+pub(crate) fn candidates(
+    py: Python,
+    ctx: &Context,
+    prefix: &str,
+    modules: &ModuleCache,
+) -> Vec<String> {
+    let mut names = Vec::new();
+    match ctx {
+        Context::Name => {
+            if let Some(dict) = main_dict(py) {
+                extend_names(&mut names, Ok(dict.keys()), prefix);
+            }
+            if let Some(dict) = builtins_dict(py) {
+                extend_names(&mut names, Ok(dict.keys()), prefix);
+            }
+            if let Ok(keyword) = PyModule::import_bound(py, "keyword") {
+                for list in ["kwlist", "softkwlist"] {
+                    extend_names(
+                        &mut names,
+                        keyword.getattr(list).and_then(|l| Ok(l.downcast_into()?)),
+                        prefix,
+                    );
+                }
+            }
+        }
+        Context::Attr(chain) => {
+            if let Some(obj) = resolve(py, chain) {
+                extend_names(&mut names, obj.dir(), prefix);
+            }
+        }
+        Context::Module(chain) => {
+            modules.modules(py, chain, &mut names);
+            names.retain(|name| name.starts_with(prefix));
+        }
+        Context::Member(chain) => {
+            modules.modules(py, chain, &mut names);
+            modules.members(py, chain, &mut names);
+            names.retain(|name| name.starts_with(prefix));
+        }
+    }
+    if !prefix.starts_with('_') {
+        names.retain(|name| !name.starts_with('_'));
+    }
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+mod test {
+    #[test]
+    fn test_context() {
+        use super::*;
+        use ruff_python_parser::{parse_unchecked, Mode};
+        let ctx = |line: &str| {
+            let parsed = parse_unchecked(line, Mode::Module);
+            context(&parsed, line, line.len())
+                .map(|(start, ctx)| (start, format!("{ctx:?}")))
+        };
+        assert_eq!(ctx("pri"), Some((0, "Name".into())));
+        assert_eq!(ctx("x = foo.ad"), Some((8, r#"Attr(["foo"])"#.into())));
+        assert_eq!(ctx("os.path."), Some((8, r#"Attr(["os", "path"])"#.into())));
+        assert_eq!(ctx("f(x).y"), None);
+        assert_eq!(ctx("'abc'.up"), None);
+        assert_eq!(ctx("x = "), None);
+        assert_eq!(ctx("import o"), Some((7, "Module([])".into())));
+        assert_eq!(ctx("import sys, os.pa"), Some((15, r#"Module(["os"])"#.into())));
+        assert_eq!(ctx("import os as o"), None);
+        assert_eq!(ctx("from json.de"), Some((10, r#"Module(["json"])"#.into())));
+        assert_eq!(ctx("from json import "), Some((17, r#"Member(["json"])"#.into())));
+        assert_eq!(ctx("from . import "), None);
+        assert_eq!(ctx("from json imp"), Some((10, "Name".into())));
+        assert!(tab_completes("foo.", 4));
+        assert!(tab_completes("  fo", 4));
+        assert!(tab_completes("from json import ", 17));
+        assert!(!tab_completes("  ", 2));
+        assert!(!tab_completes("x = 1", 5));
+    }
+    #[test]
+    fn test_candidates() {
+        use super::*;
+        #[pyfunction]
+        fn add_one(x: i64) -> i64 {
+            x + 1
+        }
+        fn host(m: &Bound<PyModule>) -> PyResult<()> {
+            m.add_function(wrap_pyfunction!(add_one, m)?)?;
+            m.add("version", "1.0")
+        }
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            py::register(py, &[py::Module::new("host", host, Vec::new)]).expect("msg");
+            let mut modules = ModuleCache::new();
+            modules.refresh(py);
+            py.run_bound("import host", None, None).expect("msg");
+            assert_eq!(
+                candidates(py, &Context::Attr(vec!["host"]), "", &modules),
+                vec!["add_one", "version"]
+            );
+            assert_eq!(candidates(py, &Context::Name, "whi", &modules), vec!["while"]);
+            assert!(candidates(py, &Context::Name, "ho", &modules)
+                .contains(&"host".to_owned()));
+            assert!(candidates(py, &Context::Module(vec![]), "ho", &modules)
+                .contains(&"host".to_owned()));
+            assert_eq!(
+                candidates(py, &Context::Module(vec!["json"]), "dec", &modules),
+                vec!["decoder"]
+            );
+            assert!(candidates(py, &Context::Member(vec!["json"]), "", &modules)
+                .contains(&"encoder".to_owned()));
+        });
+        let mut names = Vec::new();
+        source_members(
+            "import os.path, sys as s\nfrom x import y as z, w\ndef f(): pass\nclass C: pass\nA: int = 1\nb = c = 2\nif A:\n    d = 3",
+            &mut names,
+        );
+        assert_eq!(names, vec!["os", "s", "z", "w", "f", "C", "A", "b", "c"]);
+    }
+}
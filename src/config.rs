@@ -0,0 +1,547 @@
+use crate::{
+    args::Flag, keys, theme::Theme, HISTORY_SIZE, PROMPT1, PROMPT1_ERR, PROMPT1_OK,
+    PROMPT2, PROMPT2_OK, TERMINATE_N,
+};
+use rustyline::{KeyCode, KeyEvent, Modifiers};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub(crate) enum ConfigError {
+    #[error("{0}: {1}")]
+    IO(PathBuf, std::io::Error),
+    #[error("{0}: {1}")]
+    Toml(PathBuf, toml::de::Error),
+    #[error("{0}: {1}")]
+    Invalid(PathBuf, InvalidConfig),
+}
+
+/// A setting that is well-formed but unusable, in the config file or `ShellBuilder`
+#[derive(Error, Debug)]
+pub(crate) enum InvalidConfig {
+    #[error("prompt.{0} should be plain text, style it in prompt.{0}_ok")]
+    StyledPrompt(&'static str),
+    #[error("prompt.{0} is {1} columns wide, but prompt.{2} is {3}")]
+    PromptWidth(&'static str, usize, &'static str, usize),
+    #[error("indent should be spaces or a tab")]
+    Indent,
+    #[error("keys.\"{0}\": {1}")]
+    Key(String, String),
+    #[error("keys.\"{0}\" conflicts with keys.\"{1}\"")]
+    KeyConflict(String, String),
+}
+
+/// Shell configuration from `--config`, or `$XDG_CONFIG_HOME/pyapp/config.toml`
+/// unless `-I`. Every field is optional and falls back to the built-in default.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    /// commands shown and executed when the shell starts
+    pub(crate) init_cmds: Vec<String>,
+    /// extra interrupts (Ctrl-C / Ctrl-D) needed to exit the shell
+    pub(crate) terminate_n: u8,
+    /// built-in theme name or theme file, relative to the config file,
+    /// overridden by `--theme`
+    pub(crate) theme: String,
+    /// show the frames of pyapp and `runpy` in tracebacks
+    pub(crate) full_traceback: bool,
+    /// indent unit of the editor, spaces or `"\t"`
+    pub(crate) indent: String,
+    /// key map of the editor, switched by `%mode`
+    pub(crate) edit_mode: EditMode,
+    pub(crate) prompt: Prompt,
+    pub(crate) history: History,
+    pub(crate) keys: Keys,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Prompt {
+    /// primary prompt in plain text, used for the line layout
+    pub(crate) ps1: String,
+    /// primary prompt after success, same width as `ps1`
+    pub(crate) ps1_ok: String,
+    /// primary prompt after error, same width as `ps1`
+    pub(crate) ps1_err: String,
+    /// continuation prompt in plain text, used for the line layout
+    pub(crate) ps2: String,
+    /// styled continuation prompt, same width as `ps2`
+    pub(crate) ps2_ok: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct History {
+    /// history file, overridden by `--history` and PYAPP_HISTORY,
+    /// an empty path disables it
+    pub(crate) file: Option<PathBuf>,
+    /// max number of entries
+    pub(crate) size: usize,
+}
+
+/// Key bindings by key sequence, like `"ctrl-x ctrl-e"`, see [`keys::parse`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(transparent)]
+pub(crate) struct Keys(BTreeMap<String, KeyAction>);
+
+/// Action bound to a [`Key`]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyAction {
+    /// keep the binding of rustyline
+    Default,
+    /// complete after an identifier, indent otherwise
+    CompleteOrIndent,
+    Complete,
+    Indent,
+    Dedent,
+    /// insert a newline without accepting the cell
+    Newline,
+    AcceptLine,
+    /// pick a history entry to edit by a fuzzy search over the whole cells
+    HistorySearch,
+    /// call the python function of the dotted name with the buffer and the
+    /// cursor, a returned string replaces the buffer,
+    /// e.g. `{ python = "mymod.on_key" }` in the config file
+    Python(String),
+}
+
+/// Key that can be bound to a [`KeyAction`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Tab,
+    BackTab,
+    CtrlS,
+    CtrlR,
+    /// key sequence like `"alt-enter"` or `"ctrl-x ctrl-e"`
+    Seq(&'static str),
+}
+
+/// Key map of the editor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EditMode {
+    #[default]
+    Emacs,
+    Vi,
+}
+
+impl Key {
+    fn name(&self) -> &'static str {
+        match *self {
+            Self::Tab => "tab",
+            Self::BackTab => "backtab",
+            Self::CtrlS => "ctrl-s",
+            Self::CtrlR => "ctrl-r",
+            Self::Seq(seq) => seq,
+        }
+    }
+}
+
+impl fmt::Display for KeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Default => "default",
+            Self::CompleteOrIndent => "complete-or-indent",
+            Self::Complete => "complete",
+            Self::Indent => "indent",
+            Self::Dedent => "dedent",
+            Self::Newline => "newline",
+            Self::AcceptLine => "accept-line",
+            Self::HistorySearch => "history-search",
+            Self::Python(name) => return write!(f, "python {name}"),
+        })
+    }
+}
+
+impl EditMode {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Emacs => "emacs",
+            Self::Vi => "vi",
+        }
+    }
+}
+
+impl Default for Config {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            init_cmds: Vec::new(),
+            terminate_n: TERMINATE_N,
+            theme: "dark".to_owned(),
+            full_traceback: false,
+            indent: "    ".to_owned(),
+            edit_mode: EditMode::default(),
+            prompt: Prompt::default(),
+            history: History::default(),
+            keys: Keys::default(),
+        }
+    }
+}
+
+impl Default for Prompt {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            ps1: PROMPT1.to_owned(),
+            ps1_ok: PROMPT1_OK.to_owned(),
+            ps1_err: PROMPT1_ERR.to_owned(),
+            ps2: PROMPT2.to_owned(),
+            ps2_ok: PROMPT2_OK.to_owned(),
+        }
+    }
+}
+
+impl Default for History {
+    #[inline]
+    fn default() -> Self {
+        Self { file: None, size: HISTORY_SIZE }
+    }
+}
+
+impl Default for Keys {
+    #[inline]
+    fn default() -> Self {
+        Self(BTreeMap::from([
+            ("tab".to_owned(), KeyAction::CompleteOrIndent),
+            ("backtab".to_owned(), KeyAction::Dedent),
+            ("ctrl-s".to_owned(), KeyAction::Newline),
+            ("ctrl-r".to_owned(), KeyAction::HistorySearch),
+        ]))
+    }
+}
+
+impl Keys {
+    pub(crate) fn bind(&mut self, key: Key, action: KeyAction) {
+        self.0.insert(key.name().to_owned(), action);
+    }
+    pub(crate) fn get(&self, name: &str) -> Option<&KeyAction> {
+        self.0.get(name)
+    }
+    /// The bound keys with their key sequences, a sequence can't start another
+    /// one, and Enter is kept for the shell
+    pub(crate) fn sequences(
+        &self,
+    ) -> Result<Vec<(&str, Vec<KeyEvent>, &KeyAction)>, InvalidConfig> {
+        let mut bound: Vec<(&str, Vec<KeyEvent>, &KeyAction)> = Vec::new();
+        for (name, action) in &self.0 {
+            if *action == KeyAction::Default {
+                continue;
+            }
+            let key_err = |msg| InvalidConfig::Key(name.clone(), msg);
+            let seq = keys::parse(name).map_err(key_err)?;
+            if seq == [KeyEvent(KeyCode::Enter, Modifiers::NONE)] {
+                return Err(key_err("Enter is bound by the shell".to_owned()));
+            }
+            if let Some((other, ..)) = bound
+                .iter()
+                .find(|(_, other, _)| other.starts_with(&seq) || seq.starts_with(other))
+            {
+                return Err(InvalidConfig::KeyConflict(
+                    (*other).to_owned(),
+                    name.clone(),
+                ));
+            }
+            bound.push((name.as_str(), seq, action));
+        }
+        Ok(bound)
+    }
+}
+
+impl Config {
+    /// Read the config file over `base`, which is valid, and resolve the history
+    /// file with `flag`
+    pub(crate) fn load(flag: &Flag, base: Self) -> Result<Self, ConfigError> {
+        let mut config = match config_path(flag) {
+            Some(path) => {
+                let mut config = Self::from_file(&path, &base)?;
+                if config.theme != base.theme && Theme::builtin(&config.theme).is_none() {
+                    if let Some(dir) = path.parent() {
+                        config.theme = dir.join(&config.theme).to_string_lossy().into();
+                    }
+                }
+                config
+            }
+            None => base,
+        };
+        if let Some(theme) = &flag.theme {
+            config.theme = theme.clone();
+        }
+        config.history.file =
+            history_path(flag, config.history.file.take(), |var| std::env::var_os(var));
+        Ok(config)
+    }
+    pub(crate) fn from_file(path: &Path, base: &Self) -> Result<Self, ConfigError> {
+        let s =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::IO(path.into(), e))?;
+        Self::parse(&s, path, base)
+    }
+    fn parse(s: &str, path: &Path, base: &Self) -> Result<Self, ConfigError> {
+        let toml_err = |e| ConfigError::Toml(path.into(), e);
+        // check the file on its own first, so errors point to its lines
+        toml::from_str::<Self>(s).map_err(toml_err)?;
+        let mut table = toml::Table::try_from(base).expect("config is a table");
+        merge(&mut table, toml::from_str(s).map_err(toml_err)?);
+        let config: Self = table.try_into().map_err(toml_err)?;
+        config.validate().map_err(|e| ConfigError::Invalid(path.into(), e))?;
+        Ok(config)
+    }
+    pub(crate) fn validate(&self) -> Result<(), InvalidConfig> {
+        let spaces =
+            !self.indent.is_empty() && self.indent.trim_start_matches(' ').is_empty();
+        if !(spaces || self.indent == "\t") {
+            return Err(InvalidConfig::Indent);
+        }
+        self.keys.sequences()?;
+        let prompt = &self.prompt;
+        for (name, plain) in [("ps1", &prompt.ps1), ("ps2", &prompt.ps2)] {
+            if plain.contains(char::is_control) {
+                return Err(InvalidConfig::StyledPrompt(name));
+            }
+        }
+        for (name, styled, plain_name, plain) in [
+            ("ps1_ok", &prompt.ps1_ok, "ps1", &prompt.ps1),
+            ("ps1_err", &prompt.ps1_err, "ps1", &prompt.ps1),
+            ("ps2_ok", &prompt.ps2_ok, "ps2", &prompt.ps2),
+        ] {
+            let (width, plain_width) = (visible_width(styled), plain.chars().count());
+            if width != plain_width {
+                return Err(InvalidConfig::PromptWidth(
+                    name,
+                    width,
+                    plain_name,
+                    plain_width,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merge the tables of `over` into `base`, other values of `over` replace
+fn merge(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(over)) => {
+                merge(base, over)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Width of `s` without ANSI escape sequences
+fn visible_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next() == Some('[') {
+                // skip to the final byte of CSI
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else if !c.is_control() {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// `$XDG_<var>` or `$HOME/<fallback>` in the variables of `env`
+fn xdg_dir(
+    env: impl Fn(&str) -> Option<OsString>,
+    var: &str,
+    fallback: &str,
+) -> Option<PathBuf> {
+    env(var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env("HOME").map(|home| PathBuf::from(home).join(fallback)))
+}
+
+/// `--config`, then the XDG config dir unless `-I`
+fn config_path(flag: &Flag) -> Option<PathBuf> {
+    if let Some(path) = &flag.config {
+        Some(path.clone())
+    } else if flag.isolate {
+        None
+    } else {
+        xdg_dir(|var| std::env::var_os(var), "XDG_CONFIG_HOME", ".config")
+            .map(|dir| dir.join("pyapp/config.toml"))
+            .filter(|path| path.is_file())
+    }
+}
+
+/// `--history`, then PYAPP_HISTORY unless `-E`, then the config file,
+/// then the XDG data dir, an empty path disables the history file, with the
+/// variables of `env`
+fn history_path(
+    flag: &Flag,
+    file: Option<PathBuf>,
+    env: impl Fn(&str) -> Option<OsString>,
+) -> Option<PathBuf> {
+    let path = if let Some(path) = &flag.history {
+        path.clone()
+    } else if let Some(path) =
+        env("PYAPP_HISTORY").filter(|_| !(flag.ignore_env || flag.isolate))
+    {
+        path.into()
+    } else if let Some(path) = file {
+        path
+    } else {
+        xdg_dir(&env, "XDG_DATA_HOME", ".local/share")?.join("pyapp/history")
+    };
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+mod test {
+    #[test]
+    fn test_parse() {
+        use super::*;
+        let path = Path::new("config.toml");
+        let mut base = Config::default();
+        base.validate().expect("msg");
+        base.keys.bind(Key::BackTab, KeyAction::Newline);
+        let config = Config::parse(
+            r#"
+init_cmds = ["import sys"]
+terminate_n = 0
+theme = "light"
+full_traceback = true
+indent = "\t"
+edit_mode = "vi"
+
+[prompt]
+ps1 = ">>> "
+ps1_ok = "\u001b[32m>>> \u001b[m"
+ps1_err = "\u001b[31m>>> \u001b[m"
+
+[history]
+size = 10
+
+[keys]
+tab = "indent"
+ctrl-s = "default"
+"ctrl-x ctrl-e" = { python = "mymod.edit" }
+"#,
+            path,
+            &base,
+        )
+        .expect("msg");
+        assert_eq!(config.init_cmds, vec!["import sys"]);
+        assert_eq!(config.terminate_n, 0);
+        assert_eq!(config.theme, "light");
+        assert!(config.full_traceback);
+        assert_eq!(config.indent, "\t");
+        assert_eq!(config.prompt.ps1, ">>> ");
+        assert_eq!(config.prompt.ps2, PROMPT2);
+        assert_eq!(config.history.size, 10);
+        assert_eq!(config.edit_mode, EditMode::Vi);
+        assert_eq!(config.keys.get("tab"), Some(&KeyAction::Indent));
+        assert_eq!(config.keys.get("backtab"), Some(&KeyAction::Newline));
+        assert_eq!(config.keys.get("ctrl-s"), Some(&KeyAction::Default));
+        assert_eq!(config.keys.get("ctrl-r"), Some(&KeyAction::HistorySearch));
+        assert_eq!(
+            config.keys.get("ctrl-x ctrl-e"),
+            Some(&KeyAction::Python("mymod.edit".into()))
+        );
+        assert!(matches!(
+            Config::parse("[prompt]\nps1 = \">>> \"", path, &base),
+            Err(ConfigError::Invalid(
+                _,
+                InvalidConfig::PromptWidth("ps1_ok", 8, "ps1", 4)
+            ))
+        ));
+        assert!(matches!(
+            Config::parse("[prompt]\nps2 = \"\\u001b[1m...\"", path, &base),
+            Err(ConfigError::Invalid(_, InvalidConfig::StyledPrompt("ps2")))
+        ));
+        assert!(matches!(
+            Config::parse("[keys]\ntab = \"fly\"", path, &base),
+            Err(ConfigError::Toml(..))
+        ));
+        assert!(matches!(
+            Config::parse(
+                "[keys]\nctrl-q = \"indent\"\nctrl-Q = \"dedent\"",
+                path,
+                &base
+            ),
+            Err(ConfigError::Invalid(_, InvalidConfig::KeyConflict(..)))
+        ));
+        assert!(matches!(
+            Config::parse("[keys]\n\"ctrl-s ctrl-s\" = \"indent\"", path, &base),
+            Err(ConfigError::Invalid(_, InvalidConfig::KeyConflict(..)))
+        ));
+        assert!(matches!(
+            Config::parse("[keys]\nctrl-tabs = \"indent\"", path, &base),
+            Err(ConfigError::Invalid(_, InvalidConfig::Key(..)))
+        ));
+        assert!(matches!(
+            Config::parse("[keys]\nenter = \"newline\"", path, &base),
+            Err(ConfigError::Invalid(_, InvalidConfig::Key(..)))
+        ));
+        assert!(matches!(
+            Config::parse("prompts = 1", path, &base),
+            Err(ConfigError::Toml(..))
+        ));
+        assert!(matches!(
+            Config::parse("indent = \" \\t\"", path, &base),
+            Err(ConfigError::Invalid(_, InvalidConfig::Indent))
+        ));
+    }
+    #[test]
+    fn test_history_path() {
+        use super::*;
+        let env = |history: Option<&'static str>| {
+            move |var: &str| match var {
+                "XDG_DATA_HOME" => Some("data".into()),
+                "HOME" => Some("home".into()),
+                "PYAPP_HISTORY" => history.map(OsString::from),
+                _ => None,
+            }
+        };
+        let flag = |history: Option<&str>, isolate: bool| Flag {
+            history: history.map(PathBuf::from),
+            isolate,
+            ..Default::default()
+        };
+        let file = || Some(PathBuf::from("file"));
+        let path = |flag: &Flag, file, history| history_path(flag, file, env(history));
+        assert_eq!(path(&flag(Some("cli"), false), file(), None), Some("cli".into()));
+        assert_eq!(path(&flag(Some(""), false), file(), None), None);
+        assert_eq!(path(&flag(None, false), file(), Some("env")), Some("env".into()));
+        // `-I` ignores `PYAPP_HISTORY`
+        assert_eq!(path(&flag(None, true), file(), Some("env")), file());
+        assert_eq!(
+            path(&flag(None, true), None, Some("env")),
+            Some("data/pyapp/history".into())
+        );
+        assert_eq!(path(&flag(None, false), file(), Some("")), None);
+        // `$HOME` without the XDG variable
+        assert_eq!(
+            xdg_dir(
+                |var| (var == "HOME").then(|| "home".into()),
+                "XDG_DATA_HOME",
+                ".local/share"
+            ),
+            Some("home/.local/share".into())
+        );
+        assert_eq!(
+            xdg_dir(env(None), "XDG_CONFIG_HOME", ".config"),
+            Some("home/.config".into())
+        );
+    }
+}
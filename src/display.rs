@@ -0,0 +1,109 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use pyo3::{
+    prelude::*,
+    types::{PyDict, PyModule},
+};
+
+/// number of the cell in execution, the key of `Out`
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Count the next cell
+pub(crate) fn next_count() -> usize {
+    COUNT.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Number of the cell in execution
+pub(crate) fn count() -> usize {
+    COUNT.load(Ordering::Relaxed)
+}
+
+/// Write `repr(value)` to `sys.stdout`, keep it in `_`, `__`, `___` and `Out[n]`
+/// of `__main__`
+#[pyfunction]
+fn displayhook(py: Python, value: Bound<PyAny>) -> PyResult<()> {
+    if value.is_none() {
+        return Ok(());
+    }
+    remember(py, &value)?;
+    // through `sys.stdout` like the default hook, which `redirect_stdout` can capture
+    let repr = value.repr()?;
+    PyModule::import_bound(py, "sys")?
+        .getattr("stdout")?
+        .call_method1("write", (format!("{repr}\n"),))?;
+    Ok(())
+}
+
+/// Keep `value` in `_`, `__`, `___` and `Out[n]` of `__main__`
+pub(crate) fn remember(py: Python, value: &Bound<PyAny>) -> PyResult<()> {
+    let main = PyModule::import_bound(py, "__main__")?.dict();
+    for (from, to) in [("__", "___"), ("_", "__")] {
+        if let Some(last) = main.get_item(from)? {
+            main.set_item(to, last)?;
+        }
+    }
+    main.set_item("_", value)?;
+    let out = match main.get_item("Out")? {
+        Some(out) => out,
+        None => {
+            let out = PyDict::new_bound(py).into_any();
+            main.set_item("Out", &out)?;
+            out
+        }
+    };
+    out.set_item(count(), value)
+}
+
+/// Set `sys.displayhook` and `Out` for the shell
+pub(crate) fn install(py: Python) -> PyResult<()> {
+    let main = PyModule::import_bound(py, "__main__")?.dict();
+    if !main.contains("Out")? {
+        main.set_item("Out", PyDict::new_bound(py))?;
+    }
+    PyModule::import_bound(py, "sys")?
+        .setattr("displayhook", wrap_pyfunction_bound!(displayhook, py)?)
+}
+
+/// Route `value` through `sys.displayhook`
+pub(crate) fn display(py: Python, value: Bound<PyAny>) -> PyResult<()> {
+    PyModule::import_bound(py, "sys")?
+        .getattr("displayhook")?
+        .call1((value,))?;
+    Ok(())
+}
+
+mod test {
+    #[test]
+    fn test_display() {
+        use super::*;
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            install(py).expect("msg");
+            for value in [1, 2, 3, 4] {
+                next_count();
+                display(py, value.to_object(py).into_bound(py)).expect("msg");
+            }
+            display(py, py.None().into_bound(py)).expect("msg");
+            let res: (i64, i64, i64, i64) = py
+                .eval_bound("(_, __, ___, Out[2])", None, None)
+                .expect("msg")
+                .extract()
+                .expect("msg");
+            assert_eq!(res, (4, 3, 2, 2));
+            let locals = PyDict::new_bound(py);
+            py.run_bound(
+                "import contextlib, io, sys
+with contextlib.redirect_stdout(io.StringIO()) as out:
+    sys.displayhook('x')",
+                None,
+                Some(&locals),
+            )
+            .expect("msg");
+            let out: String = py
+                .eval_bound("out.getvalue()", None, Some(&locals))
+                .expect("msg")
+                .extract()
+                .expect("msg");
+            assert_eq!(out, "'x'\n");
+        });
+    }
+}
@@ -0,0 +1,47 @@
+use crate::{py::ModuleInit, stubgen::FnHint};
+use pyo3::prelude::*;
+
+/// Item of [`export`](macro@crate::export), collected from the whole program
+#[doc(hidden)]
+pub struct Export {
+    module: &'static str,
+    add: ModuleInit,
+    hint: Option<fn() -> FnHint>,
+}
+
+inventory::collect!(Export);
+
+impl Export {
+    #[inline]
+    pub const fn new(
+        module: &'static str,
+        add: ModuleInit,
+        hint: Option<fn() -> FnHint>,
+    ) -> Self {
+        Self { module, add, hint }
+    }
+}
+
+/// Dotted names of the modules with exported items
+pub(crate) fn modules() -> impl Iterator<Item = &'static str> {
+    inventory::iter::<Export>.into_iter().map(|export| export.module)
+}
+
+/// Add the items exported to the module `name`
+pub(crate) fn add(module: &Bound<PyModule>, name: &str) -> PyResult<()> {
+    for export in inventory::iter::<Export> {
+        if export.module == name {
+            (export.add)(module)?;
+        }
+    }
+    Ok(())
+}
+
+/// Type hints of the functions exported to the module `name`
+pub(crate) fn hints(name: &str) -> Vec<FnHint> {
+    inventory::iter::<Export>
+        .into_iter()
+        .filter(|export| export.module == name)
+        .filter_map(|export| export.hint.map(|hint| hint()))
+        .collect()
+}
@@ -0,0 +1,403 @@
+use crate::{highlight, theme::Theme};
+use anstyle::Style;
+use ruff_python_ast::Mod;
+use ruff_python_parser::{parse_unchecked, Mode, Parsed};
+use rustyline::{
+    completion::Completer,
+    highlight::{DisplayOnce, Highlighter, StyledBlocks},
+    hint::{Hint, Hinter},
+    history::DefaultHistory,
+    validate::Validator,
+    Cmd, ConditionalEventHandler, Editor, Event, EventContext, EventHandler, Helper,
+    KeyCode, KeyEvent, Modifiers, RepeatCount,
+};
+use std::{
+    cmp::Reverse,
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Query of the history search to open after the prompt, the buffer at Ctrl-R
+pub(crate) static QUERY: Mutex<Option<String>> = Mutex::new(None);
+
+/// Entries listed under the query
+const ROWS: usize = 8;
+/// Lines of the selected entry previewed under the list
+const PREVIEW: usize = 12;
+
+/// Leave the prompt with its buffer as the query of the history search
+pub(crate) struct SearchHandler;
+
+impl ConditionalEventHandler for SearchHandler {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        *QUERY.lock().unwrap() = Some(ctx.line().to_owned());
+        Some(Cmd::Interrupt)
+    }
+}
+
+/// Fuzzy score of `entry` for `query`, and the byte offsets of the matched chars:
+/// each word of the query matches as a subsequence anywhere in the entry, case
+/// insensitive unless it has an uppercase char, and consecutive chars and the
+/// starts of words score higher
+fn score(query: &str, entry: &str) -> Option<(i64, Vec<usize>)> {
+    let chars = entry.char_indices().collect::<Vec<_>>();
+    let (mut total, mut matched) = (0, Vec::new());
+    for word in query.split_whitespace() {
+        let (score, positions) = score_word(word, &chars)?;
+        total += score;
+        matched.extend(positions.into_iter().map(|i| chars[i].0));
+    }
+    matched.sort_unstable();
+    matched.dedup();
+    Some((total, matched))
+}
+
+/// The best of the greedy matches of `word` from each char of `chars` that
+/// matches its first char
+fn score_word(word: &str, chars: &[(usize, char)]) -> Option<(i64, Vec<usize>)> {
+    let word = word.chars().collect::<Vec<_>>();
+    let case_sensitive = word.iter().any(|c| c.is_uppercase());
+    let eq = |a: char, b: char| {
+        if case_sensitive {
+            a == b
+        } else {
+            a.to_lowercase().eq(b.to_lowercase())
+        }
+    };
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    for start in (0..chars.len()).filter(|&i| eq(chars[i].1, word[0])) {
+        let mut positions = vec![start];
+        let mut next = start + 1;
+        for &c in &word[1..] {
+            match (next..chars.len()).find(|&i| eq(chars[i].1, c)) {
+                Some(i) => positions.push(i),
+                // a later start can't match either
+                None => return best,
+            }
+            next = positions[positions.len() - 1] + 1;
+        }
+        let mut score = 0;
+        for (k, &i) in positions.iter().enumerate() {
+            score += 16;
+            if i == 0 || (!is_word(chars[i - 1].1) && is_word(chars[i].1)) {
+                score += 8;
+            }
+            match k.checked_sub(1).map(|k| positions[k]) {
+                Some(prev) if prev + 1 == i => score += 8,
+                Some(prev) => score -= (i - prev - 1) as i64,
+                None => {}
+            }
+        }
+        if best.as_ref().is_none_or(|(best, _)| score > *best) {
+            best = Some((score, positions));
+        }
+    }
+    best
+}
+
+/// Indexes of the `entries` matching `query` with their matched chars, the best
+/// first and the earlier first among equals
+fn rank(query: &str, entries: &[String]) -> Vec<(usize, Vec<usize>)> {
+    let mut matches = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, entry)| score(query, entry).map(|(s, m)| (s, idx, m)))
+        .collect::<Vec<_>>();
+    matches.sort_by_key(|&(score, idx, _)| (Reverse(score), idx));
+    matches.into_iter().map(|(_, idx, matched)| (idx, matched)).collect()
+}
+
+/// One line for `entry`: its first line cut to `width` chars with the count of
+/// the other lines, and the ranges of the `matched` chars in it
+fn row(entry: &str, matched: &[usize], width: usize) -> (String, Vec<Range<usize>>) {
+    let first = entry.lines().next().unwrap_or_default();
+    let more = match entry.trim_end().lines().count().saturating_sub(1) {
+        0 => String::new(),
+        1 => " (+1 line)".to_owned(),
+        n => format!(" (+{n} lines)"),
+    };
+    let end = first
+        .char_indices()
+        .nth(width.saturating_sub(more.chars().count()))
+        .map_or(first.len(), |(i, _)| i);
+    let bold = matched
+        .iter()
+        .filter(|&&i| i < end)
+        .map(|&i| i..i + entry[i..].chars().next().map_or(0, char::len_utf8))
+        .collect();
+    (format!("{}{more}", &first[..end]), bold)
+}
+
+/// Ranked list of the matching entries under the query, not completed by the
+/// right arrow
+struct List(String);
+
+impl Hint for List {
+    fn display(&self) -> &str {
+        &self.0
+    }
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+struct Picker {
+    /// history entries, the newest first and without duplicates
+    entries: Vec<String>,
+    /// indexes of the entries matching the query, with their matched chars
+    matches: Vec<(usize, Vec<usize>)>,
+    /// index in `matches`, moved by the handlers of the up and down keys
+    selected: Arc<AtomicUsize>,
+    theme: Theme,
+    /// bold ranges of each row shown, and the row of the selected entry
+    bold: Vec<Vec<Range<usize>>>,
+    selected_row: usize,
+    /// range of the preview in the hint shown, with its syntax
+    preview: Range<usize>,
+    parsed: Parsed<Mod>,
+    syntax: highlight::Syntax,
+}
+
+impl Helper for Picker {
+    fn update_after_edit(&mut self, line: &str, _pos: usize, _forced_refresh: bool) {
+        self.matches = rank(line, &self.entries);
+        self.selected.store(0, Ordering::Relaxed);
+    }
+    fn continuation_prompt_width<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        _prompt: &'p str,
+    ) -> usize {
+        0
+    }
+}
+
+impl Completer for Picker {
+    type Candidate = String;
+}
+
+impl Validator for Picker {}
+
+impl Hinter for Picker {
+    type Hint = List;
+    fn hint(
+        &mut self,
+        _line: &str,
+        _pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> Option<List> {
+        let width = usize::from(kdam::term::get_columns_or(80)).saturating_sub(3);
+        let selected = self
+            .selected
+            .load(Ordering::Relaxed)
+            .min(self.matches.len().saturating_sub(1));
+        self.selected.store(selected, Ordering::Relaxed);
+        let mut hint = format!("\n  {}/{}", self.matches.len(), self.entries.len());
+        // scrolled to keep the selected entry in the list
+        let first = selected.saturating_sub(ROWS - 1);
+        self.selected_row = selected - first;
+        self.bold.clear();
+        for (idx, matched) in self.matches.iter().skip(first).take(ROWS) {
+            let (row, bold) = row(&self.entries[*idx], matched, width);
+            hint += if self.bold.len() == self.selected_row { "\n> " } else { "\n  " };
+            hint += &row;
+            self.bold.push(bold);
+        }
+        let entry = match self.matches.get(selected) {
+            Some((idx, _)) => self.entries[*idx].trim_end(),
+            None => "",
+        };
+        hint += "\n";
+        hint += &"─".repeat(width.min(40));
+        hint += "\n";
+        let end = entry
+            .match_indices('\n')
+            .nth(PREVIEW - 1)
+            .map_or(entry.len(), |(i, _)| i);
+        self.preview = hint.len()..hint.len() + end;
+        hint += &entry[..end];
+        if end < entry.len() {
+            hint += "\n…";
+        }
+        self.parsed = parse_unchecked(&entry[..end], Mode::Ipython);
+        self.syntax = highlight::Syntax::new(&self.parsed);
+        Some(List(hint))
+    }
+}
+
+impl Highlighter for Picker {
+    fn highlight_char(&mut self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        true
+    }
+    fn highlight<'b, 's: 'b, 'l: 'b>(
+        &'s mut self,
+        line: &'l str,
+        _pos: usize,
+    ) -> impl 'b + DisplayOnce {
+        line
+    }
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s mut self,
+        prompt: &'p str,
+        _default: bool,
+    ) -> impl 'b + DisplayOnce {
+        prompt
+    }
+    fn highlight_hint<'b, 's: 'b, 'h: 'b>(
+        &'s mut self,
+        hint: &'h str,
+    ) -> impl 'b + DisplayOnce {
+        let mut blocks: Vec<(Style, &str)> = Vec::new();
+        // the count, then the rows, then the separator
+        for (line_idx, line) in hint[..self.preview.start].split('\n').enumerate() {
+            if line_idx > 0 {
+                blocks.push((Style::new(), "\n"));
+            }
+            let Some(bold) = line_idx.checked_sub(2).and_then(|row| self.bold.get(row))
+            else {
+                blocks.push((self.theme.hint, line));
+                continue;
+            };
+            let style = if line_idx - 2 == self.selected_row {
+                self.theme.function
+            } else {
+                self.theme.hint
+            };
+            // after the marker
+            let mut last = 0;
+            blocks.push((style, &line[..2]));
+            let line = &line[2..];
+            for range in bold.iter().filter(|range| range.end <= line.len()) {
+                blocks.push((style, &line[last..range.start]));
+                blocks.push((style.bold(), &line[range.clone()]));
+                last = range.end;
+            }
+            blocks.push((style, &line[last..]));
+        }
+        blocks.extend(highlight::styled(
+            self.parsed.tokens(),
+            &hint[self.preview.clone()],
+            &self.theme,
+            "\n",
+            &self.syntax,
+            None,
+            &[],
+        ));
+        blocks.push((self.theme.hint, &hint[self.preview.end..]));
+        StyledBlocks::new(blocks.into_iter())
+    }
+}
+
+/// Move the selection by one entry
+struct Select {
+    selected: Arc<AtomicUsize>,
+    down: bool,
+}
+
+impl ConditionalEventHandler for Select {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        _ctx: &EventContext,
+    ) -> Option<Cmd> {
+        // beyond the last entry is clamped by the hint
+        let _ = self.selected.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            if self.down {
+                n.checked_add(1)
+            } else {
+                n.checked_sub(1)
+            }
+        });
+        Some(Cmd::Repaint)
+    }
+}
+
+/// Pick one of the history `entries`, the newest first, by a fuzzy search from
+/// `query` with a preview of the selected entry, `None` if cancelled by Ctrl-C,
+/// Ctrl-G or Esc
+pub(crate) fn pick(
+    query: &str,
+    entries: impl Iterator<Item = String>,
+    theme: &Theme,
+) -> rustyline::Result<Option<String>> {
+    let mut unique = Vec::<String>::new();
+    for entry in entries {
+        if !unique.contains(&entry) {
+            unique.push(entry);
+        }
+    }
+    let selected = Arc::new(AtomicUsize::new(0));
+    let mut rl = Editor::<Picker, DefaultHistory>::new(Picker {
+        entries: unique,
+        matches: Vec::new(),
+        selected: selected.clone(),
+        theme: theme.clone(),
+        bold: Vec::new(),
+        selected_row: 0,
+        preview: 0..0,
+        parsed: parse_unchecked("", Mode::Ipython),
+        syntax: highlight::Syntax::default(),
+    })?;
+    for (key, down) in [
+        (KeyEvent(KeyCode::Up, Modifiers::NONE), false),
+        (KeyEvent::ctrl('P'), false),
+        (KeyEvent(KeyCode::Down, Modifiers::NONE), true),
+        (KeyEvent::ctrl('N'), true),
+        (KeyEvent::ctrl('R'), true),
+    ] {
+        let handler = Select { selected: selected.clone(), down };
+        rl.bind_sequence(key, EventHandler::Conditional(Box::new(handler)));
+    }
+    rl.bind_sequence(KeyEvent(KeyCode::Esc, Modifiers::NONE), Cmd::Interrupt);
+    rl.bind_sequence(KeyEvent::ctrl('G'), Cmd::Interrupt);
+    rl.helper_mut().update_after_edit(query, query.len(), true);
+    match rl.readline_with_initial("history> ", (query, "")) {
+        Ok(_) => {
+            let picker = rl.helper();
+            Ok(picker
+                .matches
+                .get(selected.load(Ordering::Relaxed))
+                .map(|(idx, _)| picker.entries[*idx].clone()))
+        }
+        Err(rustyline::error::ReadlineError::Interrupted) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+mod test {
+    #[test]
+    fn test_rank() {
+        use super::*;
+        assert_eq!(score("pit", "print(i)"), Some((16 * 3 + 8 - 2, vec![0, 2, 4])));
+        assert_eq!(score("", "x"), Some((0, vec![])));
+        assert_eq!(score("Print", "print(x)"), None);
+        assert_eq!(score("xy", "yx"), None);
+        let entries = [
+            "for i in range(3):\n    print(i)\n",
+            "def f(x):\n    return x",
+            "import os",
+            "for_each(items)",
+        ]
+        .map(String::from);
+        let order =
+            |query| rank(query, &entries).into_iter().map(|(i, _)| i).collect::<Vec<_>>();
+        assert_eq!(order("for print"), [0]);
+        assert_eq!(order("fore"), [3, 0]);
+        assert_eq!(order("ret"), [1, 3, 0]);
+        assert_eq!(order(""), [0, 1, 2, 3]);
+        let (line, bold) = row(&entries[0], &[0, 1, 4], 12);
+        assert_eq!(line, "fo (+1 line)");
+        assert_eq!(bold, [0..1, 1..2]);
+    }
+}
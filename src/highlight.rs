@@ -0,0 +1,340 @@
+use crate::{
+    semantic::{Names, Role},
+    theme::Theme,
+};
+use anstyle::Style;
+use ruff_python_ast::Mod;
+use ruff_python_parser::{Parsed, TokenKind, Tokens};
+use ruff_text_size::TextRange;
+use std::iter::once;
+
+/// Bracket pairs and identifier roles of a cell
+#[derive(Debug, Default)]
+pub(crate) struct Syntax {
+    pub(crate) brackets: Brackets,
+    pub(crate) names: Names,
+}
+
+impl Syntax {
+    pub(crate) fn new(parsed: &Parsed<Mod>) -> Self {
+        Self {
+            brackets: Brackets::new(parsed.tokens()),
+            names: Names::new(parsed),
+        }
+    }
+}
+
+/// Bracket pairs of the tokens, matched by kind with a stack
+#[derive(Debug, Default)]
+pub(crate) struct Brackets {
+    /// partner and nesting level of each matched bracket, by token index
+    pairs: Vec<Option<(usize, usize)>>,
+}
+
+impl Brackets {
+    pub(crate) fn new(tokens: &Tokens) -> Self {
+        let mut pairs = vec![None; tokens.len()];
+        let mut openers: Vec<(usize, TokenKind)> = Vec::new();
+        for (idx, token) in tokens.iter().enumerate() {
+            let kind = token.kind();
+            if let Some(closer) = closer(kind) {
+                openers.push((idx, closer));
+            } else if is_closer(kind) {
+                // a closer of another kind is left unmatched, e.g. the `]` of `(]`
+                if let Some(&(open, closer)) = openers.last() {
+                    if closer == kind {
+                        openers.pop();
+                        pairs[open] = Some((idx, openers.len()));
+                        pairs[idx] = Some((open, openers.len()));
+                    }
+                }
+            }
+        }
+        Self { pairs }
+    }
+    /// Token indexes of the matched bracket under the cursor at `pos`, or just
+    /// before it, and of its partner
+    pub(crate) fn at(&self, tokens: &Tokens, pos: usize) -> Option<(usize, usize)> {
+        let bracket = |at: fn(TextRange) -> usize| {
+            tokens.iter().position(|token| {
+                let (kind, range) = token.as_tuple();
+                at(range) == pos && (closer(kind).is_some() || is_closer(kind))
+            })
+        };
+        let idx = bracket(|range| range.start().to_usize())
+            .or_else(|| bracket(|range| range.end().to_usize()))?;
+        self.pairs[idx].map(|(partner, _)| (idx, partner))
+    }
+}
+
+/// Closer of the opening bracket `kind`
+fn closer(kind: TokenKind) -> Option<TokenKind> {
+    match kind {
+        TokenKind::Lpar => Some(TokenKind::Rpar),
+        TokenKind::Lsqb => Some(TokenKind::Rsqb),
+        TokenKind::Lbrace => Some(TokenKind::Rbrace),
+        _ => None,
+    }
+}
+
+fn is_closer(kind: TokenKind) -> bool {
+    matches!(kind, TokenKind::Rpar | TokenKind::Rsqb | TokenKind::Rbrace)
+}
+
+/// Styled pieces of `line` by its `tokens`, with each newline written as `newline`,
+/// the brackets and identifiers styled by their `syntax`, and the bracket pair at
+/// `cursor` and the pieces within `errors` underlined
+pub(crate) fn styled<'a>(
+    tokens: &'a Tokens,
+    line: &'a str,
+    theme: &'a Theme,
+    newline: &'a str,
+    syntax: &'a Syntax,
+    cursor: Option<usize>,
+    errors: &'a [TextRange],
+) -> impl Iterator<Item = (Style, &'a str)> + 'a {
+    let mut last_end = 0;
+    let mut last_kind = TokenKind::Name;
+    let Syntax { brackets, names } = syntax;
+    let active = cursor.and_then(|pos| brackets.at(tokens, pos));
+    let underline = move |style: Style, start: usize, end: usize| {
+        if errors
+            .iter()
+            .any(|error| error.start().to_usize() < end && start < error.end().to_usize())
+        {
+            style.underline()
+        } else {
+            style
+        }
+    };
+    tokens
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, token)| {
+            let (kind, range) = token.as_tuple();
+            if range.len().to_u32() == 0 {
+                None
+            } else {
+                Some((idx, kind, range))
+            }
+        })
+        .chain(once((
+            0,
+            TokenKind::EndOfFile,
+            TextRange::new((line.len() as u32).into(), (line.len() as u32).into()),
+        )))
+        .flat_map(move |(idx, kind, range)| {
+            let term = match kind {
+                TokenKind::Newline | TokenKind::NonLogicalNewline => newline,
+                _ => &line[range],
+            };
+            // soft keywords used as names too, e.g. `match = 1`
+            let role = match kind {
+                TokenKind::Name
+                | TokenKind::Match
+                | TokenKind::Case
+                | TokenKind::Type => names.get(range),
+                _ => None,
+            };
+            let style = match role {
+                Some(role) => name_style(role, term, theme),
+                None => match kind {
+                    TokenKind::Name => match last_kind {
+                        TokenKind::Def => theme.function,
+                        TokenKind::Class => theme.class,
+                        _ => match term {
+                            "self" | "super" => theme.key1,
+                            _ => {
+                                if term.chars().all(|c| c.is_ascii_uppercase()) {
+                                    theme.key1
+                                } else {
+                                    if let Some(next_token) = tokens.get(idx + 1) {
+                                        match next_token.kind() {
+                                            TokenKind::Lpar => theme.function,
+                                            _ => theme.blank,
+                                        }
+                                    } else {
+                                        theme.blank
+                                    }
+                                }
+                            }
+                        },
+                    },
+                    TokenKind::Lpar
+                    | TokenKind::Lsqb
+                    | TokenKind::Lbrace
+                    | TokenKind::Rpar
+                    | TokenKind::Rsqb
+                    | TokenKind::Rbrace => match brackets.pairs[idx] {
+                        Some((_, level)) => {
+                            let style = theme.bracket(level as i32);
+                            if active.is_some_and(|(a, b)| idx == a || idx == b) {
+                                style.underline()
+                            } else {
+                                style
+                            }
+                        }
+                        None => theme.unknown,
+                    },
+                    TokenKind::From
+                    | TokenKind::Import
+                    | TokenKind::Def
+                    | TokenKind::Class
+                    | TokenKind::Equal
+                    | TokenKind::EqEqual
+                    | TokenKind::NotEqual
+                    | TokenKind::LessEqual
+                    | TokenKind::GreaterEqual
+                    | TokenKind::DoubleStarEqual
+                    | TokenKind::PlusEqual
+                    | TokenKind::MinusEqual
+                    | TokenKind::StarEqual
+                    | TokenKind::SlashEqual
+                    | TokenKind::PercentEqual
+                    | TokenKind::AmperEqual
+                    | TokenKind::VbarEqual
+                    | TokenKind::CircumflexEqual
+                    | TokenKind::LeftShiftEqual
+                    | TokenKind::RightShiftEqual
+                    | TokenKind::DoubleSlash
+                    | TokenKind::DoubleSlashEqual
+                    | TokenKind::ColonEqual
+                    | TokenKind::At
+                    | TokenKind::AtEqual
+                    | TokenKind::Elif
+                    | TokenKind::Else
+                    | TokenKind::For
+                    | TokenKind::If
+                    | TokenKind::In
+                    | TokenKind::Plus
+                    | TokenKind::Minus
+                    | TokenKind::Star
+                    | TokenKind::Slash
+                    | TokenKind::Vbar
+                    | TokenKind::Amper
+                    | TokenKind::Less
+                    | TokenKind::Greater
+                    | TokenKind::Percent
+                    | TokenKind::Tilde
+                    | TokenKind::CircumFlex
+                    | TokenKind::LeftShift
+                    | TokenKind::RightShift
+                    | TokenKind::Dot
+                    | TokenKind::DoubleStar
+                    | TokenKind::As
+                    | TokenKind::Assert
+                    | TokenKind::Async
+                    | TokenKind::Await
+                    | TokenKind::Break
+                    | TokenKind::Continue
+                    | TokenKind::Del
+                    | TokenKind::Except
+                    | TokenKind::Global
+                    | TokenKind::Is
+                    | TokenKind::Lambda
+                    | TokenKind::Finally
+                    | TokenKind::Nonlocal
+                    | TokenKind::Not
+                    | TokenKind::Pass
+                    | TokenKind::Raise
+                    | TokenKind::Return
+                    | TokenKind::Try
+                    | TokenKind::While
+                    | TokenKind::With
+                    | TokenKind::Yield
+                    | TokenKind::Case
+                    | TokenKind::And
+                    | TokenKind::Or
+                    | TokenKind::Match => theme.key2,
+                    TokenKind::String
+                    | TokenKind::FStringStart
+                    | TokenKind::FStringMiddle
+                    | TokenKind::FStringEnd => theme.string,
+                    TokenKind::Int
+                    | TokenKind::Float
+                    | TokenKind::Complex
+                    | TokenKind::Ellipsis
+                    | TokenKind::True
+                    | TokenKind::False
+                    | TokenKind::None
+                    | TokenKind::Type => theme.key1,
+                    TokenKind::Comment => theme.comment,
+                    TokenKind::Comma
+                    | TokenKind::Unknown
+                    | TokenKind::IpyEscapeCommand
+                    | TokenKind::Exclamation
+                    | TokenKind::Colon => theme.blank,
+                    TokenKind::Indent
+                    | TokenKind::Dedent
+                    | TokenKind::Newline
+                    | TokenKind::NonLogicalNewline
+                    | TokenKind::EndOfFile => Style::new(),
+                    TokenKind::Semi | TokenKind::Question | TokenKind::Rarrow => {
+                        theme.symbol
+                    }
+                },
+            };
+            last_kind = kind;
+            let (start, end) = (range.start().to_usize(), range.end().to_usize());
+            let term_style = match kind {
+                TokenKind::Newline | TokenKind::NonLogicalNewline => style,
+                _ => underline(style, start, end),
+            };
+            let out = core::iter::once((
+                underline(style, last_end, start),
+                &line[last_end..start],
+            ))
+            .chain(core::iter::once((term_style, term)));
+            last_end = end;
+            out
+        })
+}
+
+/// Style of an identifier by its `role`, with `self`, `super` and the constants
+/// as before
+fn name_style(role: Role, term: &str, theme: &Theme) -> Style {
+    match role {
+        _ if matches!(term, "self" | "super") => theme.key1,
+        Role::Function => theme.function,
+        Role::Class => theme.class,
+        Role::Parameter => theme.parameter,
+        Role::Builtin => theme.builtin,
+        Role::Module => theme.module,
+        Role::Decorator => theme.decorator,
+        Role::Attribute => theme.attribute,
+        Role::Name if term.chars().all(|c| c.is_ascii_uppercase()) => theme.key1,
+        Role::Name => theme.blank,
+    }
+}
+
+mod test {
+    #[test]
+    fn test_brackets() {
+        use super::*;
+        use ruff_python_parser::{parse_unchecked, Mode};
+        // the levels of the brackets, `None` for unmatched
+        let levels = |line: &str| {
+            let parsed = parse_unchecked(line, Mode::Module);
+            let brackets = Brackets::new(parsed.tokens());
+            parsed
+                .tokens()
+                .iter()
+                .zip(&brackets.pairs)
+                .filter(|(token, _)| {
+                    closer(token.kind()).is_some() || is_closer(token.kind())
+                })
+                .map(|(_, pair)| pair.map(|(_, level)| level))
+                .collect::<Vec<_>>()
+        };
+        let (l0, l1, l2, l3) = (Some(0), Some(1), Some(2), Some(3));
+        assert_eq!(levels(" ( ((()())))"), [l0, l1, l2, l3, l3, l3, l3, l2, l1, l0]);
+        assert_eq!(levels("(]"), [None, None]);
+        assert_eq!(levels("[(])"), [None, l1, None, l1]);
+        assert_eq!(levels("f(x[0]))"), [l0, l1, l1, l0, None]);
+        let parsed = parse_unchecked("f(x[0])", Mode::Module);
+        let brackets = Brackets::new(parsed.tokens());
+        assert_eq!(brackets.at(parsed.tokens(), 7), Some((6, 1)));
+        assert_eq!(brackets.at(parsed.tokens(), 3), Some((3, 5)));
+        assert_eq!(brackets.at(parsed.tokens(), 0), None);
+    }
+}
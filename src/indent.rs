@@ -0,0 +1,220 @@
+use ruff_python_ast::Mod;
+use ruff_python_parser::{
+    LexicalErrorType, ParseError, ParseErrorType, Parsed, TokenKind, Tokens,
+};
+
+/// The cell needs more lines: an unfinished block, an unclosed bracket
+/// or a line continuation
+pub(crate) fn is_incomplete(parsed: &Parsed<Mod>) -> bool {
+    let mut incomplete = false;
+    for token in parsed.tokens().iter().rev() {
+        let (kind, range) = token.as_tuple();
+        match kind {
+            TokenKind::Dedent => incomplete = true,
+            TokenKind::NonLogicalNewline | TokenKind::Newline => {
+                // a block ends with an empty line
+                if incomplete {
+                    incomplete = range.len().to_u32() == 0
+                }
+                break;
+            }
+            _ => break,
+        }
+    }
+    incomplete || parsed.errors().iter().any(|error| is_incomplete_error(&error.error))
+}
+
+fn is_incomplete_error(error: &ParseErrorType) -> bool {
+    match error {
+        ParseErrorType::OtherError(s) => s.starts_with("Expected an indented"),
+        ParseErrorType::Lexical(
+            LexicalErrorType::Eof | LexicalErrorType::LineContinuationError,
+        ) => true,
+        _ => false,
+    }
+}
+
+/// Syntax errors of `text` to show while typing, without the ones that only mean
+/// the cell needs more lines, or follow from that at its end
+pub(crate) fn errors<'p>(
+    parsed: &'p Parsed<Mod>,
+    text: &str,
+) -> impl Iterator<Item = &'p ParseError> {
+    let end = if is_incomplete(parsed) { text.trim_end().len() } else { usize::MAX };
+    parsed.errors().iter().filter(move |error| {
+        !is_incomplete_error(&error.error) && error.location.end().to_usize() < end
+    })
+}
+
+/// Enter at the end of `text`: the last line with `else`, `elif`, `except`
+/// or `finally` moved back to its block if needed, and the indentation of
+/// the next line, one `unit` deeper after `:` or per open bracket, and one
+/// shallower after `return`, `pass`, `break`, `continue` or `raise`
+pub(crate) fn newline(
+    tokens: &Tokens,
+    text: &str,
+    unit: &str,
+) -> (Option<String>, String) {
+    // first token and its offset of each logical line
+    let mut lines: Vec<(TokenKind, usize)> = Vec::new();
+    let (mut depth, mut at_start, mut last) = (0_usize, true, None);
+    for token in tokens.iter() {
+        let (kind, range) = token.as_tuple();
+        match kind {
+            TokenKind::Newline => at_start = true,
+            TokenKind::NonLogicalNewline
+            | TokenKind::Comment
+            | TokenKind::Indent
+            | TokenKind::Dedent => {}
+            _ => {
+                if at_start {
+                    lines.push((kind, range.start().to_usize()));
+                    at_start = false;
+                }
+                last = Some(kind);
+                match kind {
+                    TokenKind::Lpar | TokenKind::Lsqb | TokenKind::Lbrace => depth += 1,
+                    TokenKind::Rpar | TokenKind::Rsqb | TokenKind::Rbrace => {
+                        depth = depth.saturating_sub(1)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    let (&(kind, start), previous) = match lines.split_last() {
+        Some(split) => split,
+        None => return (None, leading(line_of(text, text.len())).to_owned()),
+    };
+    let line = line_of(text, start);
+    let mut indent = leading(line).to_owned();
+    let mut current = None;
+    if matches!(
+        kind,
+        TokenKind::Else | TokenKind::Elif | TokenKind::Except | TokenKind::Finally
+    ) && !text[start..].contains('\n')
+    {
+        if let Some(block) = block_indent(previous, text, kind, &indent) {
+            current = Some(format!("{block}{}", &line[indent.len()..]));
+            indent = block.to_owned();
+        }
+    }
+    let next = if depth > 0 {
+        indent + &unit.repeat(depth)
+    } else if last == Some(TokenKind::Colon) {
+        indent + unit
+    } else if matches!(
+        kind,
+        TokenKind::Return
+            | TokenKind::Pass
+            | TokenKind::Break
+            | TokenKind::Continue
+            | TokenKind::Raise
+    ) {
+        let n = if indent.ends_with('\t') { 1 } else { unit.len().min(indent.len()) };
+        indent.truncate(indent.len() - n);
+        indent
+    } else {
+        indent
+    };
+    (current, next)
+}
+
+/// Indentation of the block that `kind` continues, e.g. the `if` of an `else`,
+/// `None` if it is already aligned or there is no such block
+fn block_indent<'t>(
+    lines: &[(TokenKind, usize)],
+    text: &'t str,
+    kind: TokenKind,
+    indent: &str,
+) -> Option<&'t str> {
+    let blocks: &[TokenKind] = match kind {
+        TokenKind::Elif => &[TokenKind::If, TokenKind::Elif],
+        TokenKind::Else => &[
+            TokenKind::If,
+            TokenKind::Elif,
+            TokenKind::For,
+            TokenKind::While,
+            TokenKind::Try,
+            TokenKind::Except,
+        ],
+        TokenKind::Except => &[TokenKind::Try, TokenKind::Except],
+        _ => &[TokenKind::Try, TokenKind::Except, TokenKind::Else],
+    };
+    // the statements at or above the level of `kind` close the deeper blocks
+    let mut max_width = indent.len();
+    for &(first, start) in lines.iter().rev() {
+        let block = leading(line_of(text, start));
+        if block.len() > max_width {
+            continue;
+        }
+        if blocks.contains(&first) {
+            return (block.len() < indent.len()).then_some(block);
+        }
+        max_width = block.len().checked_sub(1)?;
+    }
+    None
+}
+
+/// The physical line of `text` at the byte `offset`
+fn line_of(text: &str, offset: usize) -> &str {
+    let start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+    &text[start..end]
+}
+
+/// Leading spaces and tabs of `line`
+fn leading(line: &str) -> &str {
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+mod test {
+    #[test]
+    fn test_newline() {
+        use super::*;
+        use ruff_python_parser::{parse_unchecked, Mode};
+        let newline = |text: &str, unit: &str| {
+            let parsed = parse_unchecked(text, Mode::Ipython);
+            newline(parsed.tokens(), text, unit)
+        };
+        assert_eq!(newline("x = 1", "    "), (None, "".into()));
+        assert_eq!(newline("for i in x:", "    "), (None, "    ".into()));
+        assert_eq!(newline("if x:  # why", "\t"), (None, "\t".into()));
+        assert_eq!(newline("def f():\n    x = [1,", "    "), (None, "        ".into()));
+        assert_eq!(newline("def f():\n    return 1", "    "), (None, "".into()));
+        assert_eq!(newline("while 1:\n\tbreak", "\t"), (None, "".into()));
+        assert_eq!(
+            newline("if x:\n    pass\n    else:", "    "),
+            (Some("else:".into()), "    ".into())
+        );
+        assert_eq!(
+            newline("for i in x:\n    if i:\n        y = i\n        else:", "    "),
+            (Some("    else:".into()), "        ".into())
+        );
+        assert_eq!(
+            newline("try:\n    if x:\n        y\n    except E:", "    "),
+            (Some("except E:".into()), "    ".into())
+        );
+        // `else` of the inner `if`, already aligned
+        assert_eq!(
+            newline("if x:\n    if y:\n        z\n    else:", "    "),
+            (None, "        ".into())
+        );
+    }
+    #[test]
+    fn test_errors() {
+        use super::*;
+        use ruff_python_parser::{parse_unchecked, Mode};
+        use ruff_text_size::TextSize;
+        let errors = |text: &str| {
+            let parsed = parse_unchecked(text, Mode::Ipython);
+            errors(&parsed, text)
+                .map(|error| error.location.start())
+                .collect::<Vec<_>>()
+        };
+        assert!(errors("if x:").is_empty());
+        assert!(errors("f(1,").is_empty());
+        assert!(errors("x = 1 + \\").is_empty());
+        assert_eq!(errors("x = = 1").first(), Some(&TextSize::new(4)));
+    }
+}
@@ -0,0 +1,793 @@
+use crate::{completion, config::Config, display, magic, py, theme::Theme, traceback};
+use core::sync::atomic::{AtomicBool, Ordering};
+use hmac::{Hmac, Mac};
+use pyo3::{exceptions::PyRuntimeError, ffi, prelude::*, types::PyModule};
+use ruff_python_parser::{parse_unchecked, Mode};
+use rustyline::history::{DefaultHistory, History};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::{
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+use thiserror::Error;
+
+const PROTOCOL_VERSION: &str = "5.3";
+/// separates the routing identities from the message
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+/// the main loop and the control thread, which relays the control channel to it
+const RELAY: &str = "inproc://control";
+
+/// a cell is running, which an `interrupt_request` interrupts
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Error, Debug)]
+pub(crate) enum KernelError {
+    #[error("{0}: {1}")]
+    IO(PathBuf, std::io::Error),
+    #[error("{0}: {1}")]
+    Json(PathBuf, serde_json::Error),
+    #[error("{0}: unsupported signature scheme '{1}', expected 'hmac-sha256'")]
+    Scheme(PathBuf, String),
+    #[error("zmq error {0}")]
+    Zmq(#[from] zmq::Error),
+    #[error("python error {0}")]
+    PyResult(#[from] PyErr),
+}
+
+/// Connection file written by jupyter, the `--f` of `pyapp kernel`
+#[derive(Debug, Deserialize)]
+struct Connection {
+    transport: String,
+    ip: String,
+    shell_port: u16,
+    iopub_port: u16,
+    stdin_port: u16,
+    control_port: u16,
+    hb_port: u16,
+    /// no signature if empty
+    #[serde(default)]
+    key: String,
+    #[serde(default)]
+    signature_scheme: String,
+}
+
+impl Connection {
+    fn from_file(path: &Path) -> Result<Self, KernelError> {
+        let s =
+            std::fs::read_to_string(path).map_err(|e| KernelError::IO(path.into(), e))?;
+        serde_json::from_str(&s).map_err(|e| KernelError::Json(path.into(), e))
+    }
+    fn endpoint(&self, port: u16) -> String {
+        match self.transport.as_str() {
+            "ipc" => format!("ipc://{}-{port}", self.ip),
+            transport => format!("{transport}://{}:{port}", self.ip),
+        }
+    }
+    fn bind(
+        &self,
+        ctx: &zmq::Context,
+        ty: zmq::SocketType,
+        port: u16,
+    ) -> Result<zmq::Socket, KernelError> {
+        let socket = ctx.socket(ty)?;
+        socket.bind(&self.endpoint(port))?;
+        Ok(socket)
+    }
+}
+
+/// HMAC of the header, parent header, metadata and content
+#[derive(Clone)]
+struct Signer {
+    mac: Option<Hmac<Sha256>>,
+}
+
+impl Signer {
+    fn new(conn: &Connection, path: &Path) -> Result<Self, KernelError> {
+        if conn.key.is_empty() {
+            return Ok(Self { mac: None });
+        }
+        if !matches!(conn.signature_scheme.as_str(), "hmac-sha256" | "") {
+            return Err(KernelError::Scheme(path.into(), conn.signature_scheme.clone()));
+        }
+        let mac = Hmac::new_from_slice(conn.key.as_bytes()).expect("any key length");
+        Ok(Self { mac: Some(mac) })
+    }
+    fn sign(&self, parts: &[&[u8]]) -> String {
+        match &self.mac {
+            Some(mac) => {
+                let mut mac = mac.clone();
+                parts.iter().for_each(|part| mac.update(part));
+                hex::encode(mac.finalize().into_bytes())
+            }
+            None => String::new(),
+        }
+    }
+    fn verify(&self, signature: &[u8], parts: &[&[u8]]) -> bool {
+        match &self.mac {
+            Some(mac) => {
+                let mut mac = mac.clone();
+                parts.iter().for_each(|part| mac.update(part));
+                hex::decode(signature).is_ok_and(|sig| mac.verify_slice(&sig).is_ok())
+            }
+            None => true,
+        }
+    }
+}
+
+/// A message of the jupyter protocol, `ids` are the routing identities
+struct Message {
+    ids: Vec<Vec<u8>>,
+    header: Value,
+    parent_header: Value,
+    metadata: Value,
+    content: Value,
+}
+
+impl Message {
+    fn new(
+        ids: Vec<Vec<u8>>,
+        session: &str,
+        msg_type: &str,
+        parent: Value,
+        content: Value,
+    ) -> Self {
+        let header = json!({
+            "msg_id": uuid::Uuid::new_v4().to_string(),
+            "session": session,
+            "username": env!("CARGO_PKG_NAME"),
+            "date": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            "msg_type": msg_type,
+            "version": PROTOCOL_VERSION,
+        });
+        Self {
+            ids,
+            header,
+            parent_header: parent,
+            metadata: json!({}),
+            content,
+        }
+    }
+    /// `None` for a malformed message or a bad signature
+    fn parse(mut frames: Vec<Vec<u8>>, signer: &Signer) -> Option<Self> {
+        let delimiter = frames.iter().position(|frame| frame == DELIMITER)?;
+        let rest = frames.split_off(delimiter + 1);
+        frames.pop();
+        let [signature, header, parent_header, metadata, content, ..] = rest.as_slice()
+        else {
+            return None;
+        };
+        if !signer.verify(
+            signature,
+            &[header, parent_header, metadata, content].map(Vec::as_slice),
+        ) {
+            return None;
+        }
+        Some(Self {
+            ids: frames,
+            header: serde_json::from_slice(header).ok()?,
+            parent_header: serde_json::from_slice(parent_header).ok()?,
+            metadata: serde_json::from_slice(metadata).ok()?,
+            content: serde_json::from_slice(content).ok()?,
+        })
+    }
+    fn msg_type(&self) -> &str {
+        self.header["msg_type"].as_str().unwrap_or_default()
+    }
+    fn reply(&self, session: &str, msg_type: &str, content: Value) -> Self {
+        Self::new(self.ids.clone(), session, msg_type, self.header.clone(), content)
+    }
+    fn into_frames(self, signer: &Signer) -> Vec<Vec<u8>> {
+        let parts = [self.header, self.parent_header, self.metadata, self.content]
+            .map(|part| part.to_string().into_bytes());
+        let signature = signer.sign(&parts.each_ref().map(Vec::as_slice));
+        let mut frames = self.ids;
+        frames.push(DELIMITER.to_vec());
+        frames.push(signature.into_bytes());
+        frames.extend(parts);
+        frames
+    }
+}
+
+/// `msg_type`, content and parent header, `None` to stop the iopub thread
+type Publish = Option<(String, Value, Value)>;
+
+/// Sender to the thread that owns the PUB socket, `parent` is the request in handling
+#[derive(Clone)]
+struct IoPub {
+    tx: mpsc::Sender<Publish>,
+    parent: Arc<Mutex<Value>>,
+}
+
+impl IoPub {
+    fn spawn(
+        socket: zmq::Socket,
+        signer: Signer,
+        session: String,
+    ) -> (Self, JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel::<Publish>();
+        let handle = thread::spawn(move || {
+            // `sys.stdout` keeps a sender, so the channel never closes by itself
+            while let Ok(Some((msg_type, content, parent))) = rx.recv() {
+                let topic = vec![msg_type.clone().into_bytes()];
+                let msg = Message::new(topic, &session, &msg_type, parent, content);
+                if let Err(e) = socket.send_multipart(msg.into_frames(&signer), 0) {
+                    eprintln!("iopub: {e}");
+                }
+            }
+        });
+        (Self { tx, parent: Arc::new(Mutex::new(json!({}))) }, handle)
+    }
+    fn send(&self, msg_type: &str, content: Value) {
+        let parent = self.parent.lock().unwrap().clone();
+        _ = self.tx.send(Some((msg_type.to_owned(), content, parent)));
+    }
+    fn status(&self, state: &str) {
+        self.send("status", json!({ "execution_state": state }));
+    }
+}
+
+/// `sys.stdout` and `sys.stderr` of the kernel, each write is a `stream` message
+#[pyclass]
+struct Stream {
+    name: &'static str,
+    iopub: IoPub,
+}
+
+#[pymethods]
+impl Stream {
+    fn write(&self, s: &str) -> usize {
+        if !s.is_empty() {
+            self.iopub.send("stream", json!({ "name": self.name, "text": s }));
+        }
+        s.chars().count()
+    }
+    fn flush(&self) {}
+    fn isatty(&self) -> bool {
+        false
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    #[getter]
+    fn encoding(&self) -> &str {
+        "utf-8"
+    }
+}
+
+/// `sys.displayhook` of the kernel, publish `execute_result` rather than print
+#[pyclass]
+struct DisplayHook {
+    iopub: IoPub,
+}
+
+#[pymethods]
+impl DisplayHook {
+    fn __call__(&self, py: Python, value: Bound<PyAny>) -> PyResult<()> {
+        if value.is_none() {
+            return Ok(());
+        }
+        display::remember(py, &value)?;
+        self.iopub.send(
+            "execute_result",
+            json!({
+                "execution_count": display::count(),
+                "data": mime_bundle(&value)?,
+                "metadata": {},
+            }),
+        );
+        Ok(())
+    }
+}
+
+/// Routing identities and header of an `execute_request` that allows stdin
+type StdinRequest = Option<(Vec<Vec<u8>>, Value)>;
+
+/// `input()` of the kernel, an `input_request` on the stdin channel to the client
+/// of the running cell
+#[pyclass]
+struct Input {
+    socket: Mutex<zmq::Socket>,
+    signer: Signer,
+    session: String,
+    request: Arc<Mutex<StdinRequest>>,
+}
+
+#[pymethods]
+impl Input {
+    #[pyo3(signature = (prompt = ""))]
+    fn __call__(&self, py: Python, prompt: &str) -> PyResult<String> {
+        let Some((ids, parent)) = self.request.lock().unwrap().clone() else {
+            return Err(PyRuntimeError::new_err(
+                "input() was called, but the frontend doesn't allow stdin",
+            ));
+        };
+        let content = json!({ "prompt": prompt, "password": false });
+        let msg = Message::new(ids, &self.session, "input_request", parent, content);
+        let zmq_err = |e: zmq::Error| PyRuntimeError::new_err(format!("zmq error {e}"));
+        let frames = msg.into_frames(&self.signer);
+        self.socket
+            .lock()
+            .unwrap()
+            .send_multipart(frames, 0)
+            .map_err(zmq_err)?;
+        loop {
+            // wake up for the interrupts while the user types
+            let ready = py
+                .allow_threads(|| self.socket.lock().unwrap().poll(zmq::POLLIN, 100))
+                .map_err(zmq_err)?;
+            py.check_signals()?;
+            if ready == 0 {
+                continue;
+            }
+            let frames =
+                self.socket.lock().unwrap().recv_multipart(0).map_err(zmq_err)?;
+            match Message::parse(frames, &self.signer) {
+                Some(reply) if reply.msg_type() == "input_reply" => {
+                    return Ok(reply.content["value"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_owned())
+                }
+                _ => eprintln!("kernel: drop an unexpected message on stdin"),
+            }
+        }
+    }
+}
+
+/// Serve the control channel on its own thread, so that an `interrupt_request`
+/// reaches a running cell, and relay the other requests to the main loop,
+/// until a `shutdown_reply`
+fn spawn_control(
+    control: zmq::Socket,
+    relay: zmq::Socket,
+    signer: Signer,
+    session: String,
+) -> JoinHandle<()> {
+    thread::spawn(move || loop {
+        let mut items =
+            [control.as_poll_item(zmq::POLLIN), relay.as_poll_item(zmq::POLLIN)];
+        match zmq::poll(&mut items, -1) {
+            Ok(_) | Err(zmq::Error::EINTR) => {}
+            Err(e) => return eprintln!("control: {e}"),
+        }
+        if items[1].is_readable() {
+            let frames = match relay.recv_multipart(0) {
+                Ok(frames) => frames,
+                Err(e) => return eprintln!("control: {e}"),
+            };
+            let shutdown = Message::parse(frames.clone(), &signer)
+                .is_some_and(|msg| msg.msg_type() == "shutdown_reply");
+            if let Err(e) = control.send_multipart(frames, 0) {
+                eprintln!("control: {e}");
+            }
+            if shutdown {
+                return;
+            }
+        }
+        if !items[0].is_readable() {
+            continue;
+        }
+        let frames = match control.recv_multipart(0) {
+            Ok(frames) => frames,
+            Err(e) => return eprintln!("control: {e}"),
+        };
+        let res = match Message::parse(frames.clone(), &signer) {
+            Some(msg) if msg.msg_type() == "interrupt_request" => {
+                if BUSY.load(Ordering::Relaxed) {
+                    // only sets a flag like the SIGINT handler, without the GIL
+                    unsafe { ffi::PyErr_SetInterrupt() };
+                }
+                let reply =
+                    msg.reply(&session, "interrupt_reply", json!({ "status": "ok" }));
+                control.send_multipart(reply.into_frames(&signer), 0)
+            }
+            _ => relay.send_multipart(frames, 0),
+        };
+        if let Err(e) = res {
+            eprintln!("control: {e}");
+        }
+    })
+}
+
+/// `repr` and the rich outputs of the `_repr_*_` methods, like IPython
+fn mime_bundle(value: &Bound<PyAny>) -> PyResult<Value> {
+    const REPRS: [(&str, &str); 5] = [
+        ("_repr_html_", "text/html"),
+        ("_repr_markdown_", "text/markdown"),
+        ("_repr_svg_", "image/svg+xml"),
+        ("_repr_latex_", "text/latex"),
+        ("_repr_json_", "application/json"),
+    ];
+    let mut data = json!({ "text/plain": value.repr()?.to_string() });
+    for (method, mime) in REPRS {
+        let Ok(repr) = value.call_method0(method) else { continue };
+        if repr.is_none() {
+            continue;
+        }
+        data[mime] = match mime {
+            "application/json" => {
+                let json = PyModule::import_bound(value.py(), "json")?;
+                serde_json::from_str(
+                    &json.call_method1("dumps", (repr,))?.extract::<String>()?,
+                )
+                .unwrap_or_default()
+            }
+            _ => repr.str()?.to_string().into(),
+        };
+    }
+    Ok(data)
+}
+
+struct Kernel {
+    session: String,
+    iopub: IoPub,
+    theme: Theme,
+    full_traceback: bool,
+    history: DefaultHistory,
+    modules: completion::ModuleCache,
+    /// the request of the running cell for `input()`
+    stdin_request: Arc<Mutex<StdinRequest>>,
+}
+
+/// Run `pyapp kernel --f <connection.json>` until a `shutdown_request`
+pub(crate) fn run(path: &Path, config: &Config, theme: Theme) -> Result<(), KernelError> {
+    let conn = Connection::from_file(path)?;
+    let signer = Signer::new(&conn, path)?;
+    let session = uuid::Uuid::new_v4().to_string();
+    let ctx = zmq::Context::new();
+    let shell = conn.bind(&ctx, zmq::ROUTER, conn.shell_port)?;
+    let relay = ctx.socket(zmq::PAIR)?;
+    relay.bind(RELAY)?;
+    let control_thread = spawn_control(
+        conn.bind(&ctx, zmq::ROUTER, conn.control_port)?,
+        relay,
+        signer.clone(),
+        session.clone(),
+    );
+    let control = ctx.socket(zmq::PAIR)?;
+    control.connect(RELAY)?;
+    let stdin = conn.bind(&ctx, zmq::ROUTER, conn.stdin_port)?;
+    let heartbeat = conn.bind(&ctx, zmq::REP, conn.hb_port)?;
+    thread::spawn(move || {
+        while let Ok(ping) = heartbeat.recv_bytes(0) {
+            _ = heartbeat.send(ping, 0);
+        }
+    });
+    let iopub_socket = conn.bind(&ctx, zmq::PUB, conn.iopub_port)?;
+    let (iopub, iopub_thread) =
+        IoPub::spawn(iopub_socket, signer.clone(), session.clone());
+    let mut kernel = Kernel {
+        session,
+        iopub,
+        theme,
+        full_traceback: config.full_traceback,
+        history: DefaultHistory::new(),
+        modules: completion::ModuleCache::new(),
+        stdin_request: Arc::new(Mutex::new(None)),
+    };
+    let input = Input {
+        socket: Mutex::new(stdin),
+        signer: signer.clone(),
+        session: kernel.session.clone(),
+        request: kernel.stdin_request.clone(),
+    };
+    Python::with_gil(|py| kernel.init(py, input))?;
+    kernel.iopub.status("starting");
+    loop {
+        let mut items =
+            [shell.as_poll_item(zmq::POLLIN), control.as_poll_item(zmq::POLLIN)];
+        match zmq::poll(&mut items, -1) {
+            Ok(_) => {}
+            // an interrupt at idle, drop the `KeyboardInterrupt` it leaves behind
+            Err(zmq::Error::EINTR) => {
+                _ = Python::with_gil(|py| py.check_signals());
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let readable = [items[1].is_readable(), items[0].is_readable()];
+        // the control channel goes first
+        for (socket, readable) in [&control, &shell].into_iter().zip(readable) {
+            if !readable {
+                continue;
+            }
+            let Some(msg) = Message::parse(socket.recv_multipart(0)?, &signer) else {
+                eprintln!("kernel: drop a malformed or unsigned message");
+                continue;
+            };
+            *kernel.iopub.parent.lock().unwrap() = msg.header.clone();
+            kernel.iopub.status("busy");
+            let (reply, shutdown) = Python::with_gil(|py| kernel.handle(py, &msg));
+            if let Some(reply) = reply {
+                socket.send_multipart(reply.into_frames(&signer), 0)?;
+            }
+            kernel.iopub.status("idle");
+            if shutdown {
+                // let the iopub thread send what is left, and the control thread
+                // relay the reply, it stops after a `shutdown_reply`
+                _ = kernel.iopub.tx.send(None);
+                _ = iopub_thread.join();
+                if std::ptr::eq(socket, &control) {
+                    _ = control_thread.join();
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Kernel {
+    fn init(&mut self, py: Python, input: Input) -> PyResult<()> {
+        py::init(py)?;
+        py::catch_sigint(py)?;
+        display::install(py)?;
+        let sys = PyModule::import_bound(py, "sys")?;
+        for name in ["stdout", "stderr"] {
+            let stream = Stream { name, iopub: self.iopub.clone() };
+            sys.setattr(name, Py::new(py, stream)?)?;
+        }
+        sys.setattr(
+            "displayhook",
+            Py::new(py, DisplayHook { iopub: self.iopub.clone() })?,
+        )?;
+        PyModule::import_bound(py, "builtins")?.setattr("input", Py::new(py, input)?)?;
+        self.modules.refresh(py);
+        Ok(())
+    }
+    /// The reply, and whether to shut down
+    fn handle(&mut self, py: Python, msg: &Message) -> (Option<Message>, bool) {
+        let content = &msg.content;
+        let (msg_type, reply) = match msg.msg_type() {
+            "kernel_info_request" => ("kernel_info_reply", kernel_info(py)),
+            "execute_request" => ("execute_reply", self.execute(py, msg)),
+            "complete_request" => ("complete_reply", self.complete(py, content)),
+            "inspect_request" => ("inspect_reply", inspect(py, content)),
+            "is_complete_request" => ("is_complete_reply", is_complete(py, content)),
+            "history_request" => {
+                ("history_reply", json!({ "status": "ok", "history": [] }))
+            }
+            "comm_info_request" => {
+                ("comm_info_reply", json!({ "status": "ok", "comms": {} }))
+            }
+            "shutdown_request" => {
+                let restart = content["restart"].as_bool().unwrap_or(false);
+                let reply = json!({ "status": "ok", "restart": restart });
+                return (Some(msg.reply(&self.session, "shutdown_reply", reply)), true);
+            }
+            msg_type => {
+                eprintln!("kernel: unsupported message type '{msg_type}'");
+                return (None, false);
+            }
+        };
+        (Some(msg.reply(&self.session, msg_type, reply)), false)
+    }
+    fn execute(&mut self, py: Python, msg: &Message) -> Value {
+        let content = &msg.content;
+        let code = content["code"].as_str().unwrap_or_default();
+        let silent = content["silent"].as_bool().unwrap_or(false);
+        let store_history = !silent && content["store_history"].as_bool().unwrap_or(true);
+        let count = if store_history { display::next_count() } else { display::count() };
+        if !silent {
+            self.iopub
+                .send("execute_input", json!({ "code": code, "execution_count": count }));
+        }
+        let filename = format!("<cell-{count}>");
+        let parsed = parse_unchecked(code, Mode::Ipython);
+        let allow_stdin = content["allow_stdin"].as_bool().unwrap_or(false);
+        *self.stdin_request.lock().unwrap() =
+            allow_stdin.then(|| (msg.ids.clone(), msg.header.clone()));
+        BUSY.store(true, Ordering::Relaxed);
+        let res = magic::run(py, code, &filename, &parsed, &self.history);
+        BUSY.store(false, Ordering::Relaxed);
+        // an interrupt that came too late for the cell is not for the next one
+        _ = py.check_signals();
+        if store_history {
+            _ = self.history.add(code);
+        }
+        match res {
+            Ok(()) => json!({
+                "status": "ok",
+                "execution_count": count,
+                "user_expressions": {},
+                "payload": [],
+            }),
+            Err(e) => {
+                let error = self.error(py, &e);
+                self.iopub.send("error", error.clone());
+                let mut reply = json!({ "status": "error", "execution_count": count });
+                reply
+                    .as_object_mut()
+                    .unwrap()
+                    .extend(error.as_object().unwrap().clone());
+                reply
+            }
+        }
+    }
+    /// `ename`, `evalue` and the lines of the colored traceback
+    fn error(&self, py: Python, err: &PyErr) -> Value {
+        let ename = err
+            .get_type_bound(py)
+            .qualname()
+            .map(|name| name.to_string())
+            .unwrap_or_default();
+        let evalue = err.value_bound(py).str().map(|s| s.to_string()).unwrap_or_default();
+        let traceback =
+            traceback::format(py, err, &self.theme, true, self.full_traceback)
+                .unwrap_or_else(|_| format!("{ename}: {evalue}"));
+        json!({ "ename": ename, "evalue": evalue, "traceback": traceback.lines().collect::<Vec<_>>() })
+    }
+    fn complete(&mut self, py: Python, content: &Value) -> Value {
+        let code = content["code"].as_str().unwrap_or_default();
+        let cursor = byte_offset(code, &content["cursor_pos"]);
+        let line_start = code[..cursor].rfind('\n').map_or(0, |idx| idx + 1);
+        let word = &code[line_start..cursor];
+        let (start, matches) =
+            if word.starts_with('%') && !word.contains(char::is_whitespace) {
+                (line_start, magic::complete(word))
+            } else {
+                let parsed = parse_unchecked(code, Mode::Ipython);
+                match completion::context(&parsed, code, cursor) {
+                    Some((start, ctx)) => {
+                        self.modules.refresh(py);
+                        let prefix = &code[start..cursor];
+                        (start, completion::candidates(py, &ctx, prefix, &self.modules))
+                    }
+                    None => (cursor, Vec::new()),
+                }
+            };
+        json!({
+            "status": "ok",
+            "matches": matches,
+            "cursor_start": code[..start].chars().count(),
+            "cursor_end": code[..cursor].chars().count(),
+            "metadata": {},
+        })
+    }
+}
+
+/// `cursor_pos` counts the unicode code points since protocol 5.2
+fn byte_offset(code: &str, cursor_pos: &Value) -> usize {
+    let chars = cursor_pos.as_u64().map_or(usize::MAX, |pos| pos as usize);
+    code.char_indices().nth(chars).map_or(code.len(), |(idx, _)| idx)
+}
+
+fn kernel_info(py: Python) -> Value {
+    let version = py.version().split_whitespace().next().unwrap_or_default().to_owned();
+    json!({
+        "status": "ok",
+        "protocol_version": PROTOCOL_VERSION,
+        "implementation": env!("CARGO_PKG_NAME"),
+        "implementation_version": env!("CARGO_PKG_VERSION"),
+        "language_info": {
+            "name": "python",
+            "version": version,
+            "mimetype": "text/x-python",
+            "file_extension": ".py",
+            "pygments_lexer": "ipython3",
+            "codemirror_mode": { "name": "ipython", "version": 3 },
+            "nbconvert_exporter": "python",
+        },
+        "banner": format!(
+            "{} {} (Python {version})",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ),
+        "help_links": [],
+    })
+}
+
+/// Signature, type and docstring of the dotted name at the cursor,
+/// or before the `(` right before it
+fn inspect(py: Python, content: &Value) -> Value {
+    let code = content["code"].as_str().unwrap_or_default();
+    let mut cursor = byte_offset(code, &content["cursor_pos"]);
+    if code[..cursor].ends_with('(') {
+        cursor -= 1;
+    }
+    let is_name = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+    let start = code[..cursor]
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !is_name(*c))
+        .map_or(0, |(idx, c)| idx + c.len_utf8());
+    let end = code[cursor..]
+        .find(|c| !is_name(c))
+        .map_or(code.len(), |idx| cursor + idx);
+    let name = code[start..end].trim_matches('.');
+    let chain: Vec<&str> = name.split('.').collect();
+    let detail = content["detail_level"].as_u64().unwrap_or(0) > 0;
+    match completion::resolve(py, &chain).map(|obj| describe(name, &obj, detail)) {
+        Some(Ok(text)) => json!({
+            "status": "ok",
+            "found": true,
+            "data": { "text/plain": text },
+            "metadata": {},
+        }),
+        _ => json!({ "status": "ok", "found": false, "data": {}, "metadata": {} }),
+    }
+}
+
+/// Like `obj?` of IPython, with the source for `obj??`
+fn describe(name: &str, obj: &Bound<PyAny>, detail: bool) -> PyResult<String> {
+    let inspect = PyModule::import_bound(obj.py(), "inspect")?;
+    let mut out = String::new();
+    if let Ok(signature) = inspect.call_method1("signature", (obj,)) {
+        out += &format!("Signature: {name}{signature}\n");
+    }
+    out += &format!("Type:      {}\n", obj.get_type().qualname()?);
+    if !obj.is_callable() {
+        out += &format!("Value:     {}\n", obj.repr()?);
+    }
+    if let Some(doc) =
+        inspect.call_method1("getdoc", (obj,))?.extract::<Option<String>>()?
+    {
+        out += &format!("Docstring:\n{doc}\n");
+    }
+    if detail {
+        if let Ok(source) = inspect.call_method1("getsource", (obj,)) {
+            out += &format!("Source:\n{source}");
+        }
+    }
+    Ok(out)
+}
+
+/// `incomplete` with the indent of the next line, like `codeop`,
+/// and the magics and `!cmd` that python rejects are `complete`
+fn is_complete(py: Python, content: &Value) -> Value {
+    let code = content["code"].as_str().unwrap_or_default();
+    let incomplete = PyModule::import_bound(py, "codeop")
+        .and_then(|codeop| codeop.getattr("compile_command"))
+        .and_then(|compile_command| py::is_incomplete_code(&compile_command, code));
+    match incomplete {
+        Ok(true) => {
+            let last = code.trim_end_matches('\n').lines().last().unwrap_or_default();
+            let mut indent: String =
+                last.chars().take_while(|c| c.is_whitespace()).collect();
+            if last.trim_end().ends_with(':') {
+                indent += "    ";
+            }
+            json!({ "status": "incomplete", "indent": indent })
+        }
+        Ok(false) => json!({ "status": "complete" }),
+        Err(_) if parse_unchecked(code, Mode::Ipython).errors().is_empty() => {
+            json!({ "status": "complete" })
+        }
+        Err(_) => json!({ "status": "invalid" }),
+    }
+}
+
+mod test {
+    #[test]
+    fn test_message() {
+        use super::*;
+        let conn: Connection = serde_json::from_str(
+            r#"{"transport": "tcp", "ip": "127.0.0.1", "shell_port": 1, "iopub_port": 2,
+                "stdin_port": 3, "control_port": 4, "hb_port": 5,
+                "key": "secret", "signature_scheme": "hmac-sha256"}"#,
+        )
+        .expect("msg");
+        assert_eq!(conn.endpoint(conn.shell_port), "tcp://127.0.0.1:1");
+        let signer = Signer::new(&conn, Path::new("kernel.json")).expect("msg");
+        let request = Message::new(
+            vec![b"client".to_vec()],
+            "s",
+            "kernel_info_request",
+            json!({}),
+            json!({}),
+        );
+        let reply = request.reply("k", "kernel_info_reply", json!({ "status": "ok" }));
+        let mut frames = reply.into_frames(&signer);
+        let parsed = Message::parse(frames.clone(), &signer).expect("msg");
+        assert_eq!(parsed.ids, vec![b"client".to_vec()]);
+        assert_eq!(parsed.msg_type(), "kernel_info_reply");
+        assert_eq!(parsed.parent_header["msg_type"], "kernel_info_request");
+        // tamper the content
+        *frames.last_mut().unwrap() = b"{}".to_vec();
+        assert!(Message::parse(frames, &signer).is_none());
+        assert_eq!(byte_offset("é = 1", &json!(1)), 2);
+        assert_eq!(byte_offset("x", &json!(5)), 1);
+    }
+}
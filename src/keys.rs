@@ -0,0 +1,168 @@
+use crate::{completion, config::EditMode};
+use pyo3::{exceptions::PyNameError, prelude::*};
+use rustyline::{
+    Cmd, ConditionalEventHandler, Event, EventContext, KeyCode, KeyEvent, Modifiers,
+    Movement, RepeatCount,
+};
+use std::sync::Mutex;
+
+/// Key bindings of the running shell by name, with their actions, for `%keys`
+pub(crate) static BINDINGS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+/// Error of a python key binding with the buffer, printed after leaving the
+/// prompt since the terminal is in raw mode while it's edited
+pub(crate) static ERROR: Mutex<Option<(PyErr, String)>> = Mutex::new(None);
+/// Edit mode of the next prompt, switched by `%mode`
+pub(crate) static EDIT_MODE: Mutex<EditMode> = Mutex::new(EditMode::Emacs);
+
+/// Key sequence like `"ctrl-x ctrl-e"`, each key is a char or a name like `tab`,
+/// `enter`, `up` or `f5`, after the modifiers `ctrl-`, `alt-` and `shift-`
+pub(crate) fn parse(seq: &str) -> Result<Vec<KeyEvent>, String> {
+    let keys = seq.split_whitespace().map(parse_key).collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err("empty key sequence".to_owned());
+    }
+    Ok(keys)
+}
+
+fn parse_key(key: &str) -> Result<KeyEvent, String> {
+    let mut mods = Modifiers::NONE;
+    let mut rest = key;
+    // a trailing `-` is the key itself, e.g. `alt--`
+    while let Some((modifier, tail)) = rest.split_once('-').filter(|(_, t)| !t.is_empty())
+    {
+        mods |= match modifier.to_ascii_lowercase().as_str() {
+            "ctrl" => Modifiers::CTRL,
+            "alt" | "meta" => Modifiers::ALT,
+            "shift" => Modifiers::SHIFT,
+            _ => return Err(format!("unknown modifier `{modifier}`")),
+        };
+        rest = tail;
+    }
+    let code = match rest.to_ascii_lowercase().as_str() {
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "enter" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "space" => KeyCode::Char(' '),
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        name => match name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            Some(n) if (1..=24).contains(&n) => KeyCode::F(n),
+            _ => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return Err(format!("unknown key `{rest}`")),
+                }
+            }
+        },
+    };
+    Ok(KeyEvent::normalize(KeyEvent(code, mods)))
+}
+
+/// Call the python function of the dotted `name` with the buffer and the cursor,
+/// a returned string replaces the buffer
+pub(crate) struct PythonHandler(pub(crate) String);
+
+impl PythonHandler {
+    fn call(&self, py: Python, line: &str, pos: usize) -> PyResult<Option<String>> {
+        let chain = self.0.split('.').collect::<Vec<_>>();
+        let callback = match completion::resolve(py, &chain) {
+            Some(callback) => callback,
+            None => match self.0.rsplit_once('.') {
+                Some((module, name)) => {
+                    PyModule::import_bound(py, module)?.getattr(name)?
+                }
+                None => {
+                    return Err(PyNameError::new_err(format!(
+                        "name '{}' is not defined",
+                        self.0
+                    )))
+                }
+            },
+        };
+        let ret = callback.call1((line, pos))?;
+        if ret.is_none() {
+            Ok(None)
+        } else {
+            ret.extract().map(Some)
+        }
+    }
+}
+
+impl ConditionalEventHandler for PythonHandler {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        Python::with_gil(|py| match self.call(py, ctx.line(), ctx.pos()) {
+            Ok(Some(text)) => Some(Cmd::Replace(Movement::WholeBuffer, Some(text))),
+            Ok(None) => Some(Cmd::Noop),
+            Err(e) => {
+                *ERROR.lock().unwrap() = Some((e, ctx.line().to_owned()));
+                Some(Cmd::Interrupt)
+            }
+        })
+    }
+}
+
+mod test {
+    #[test]
+    fn test_parse() {
+        use super::*;
+        let ctrl = |c| KeyEvent(KeyCode::Char(c), Modifiers::CTRL);
+        assert_eq!(parse("ctrl-s"), Ok(vec![ctrl('S')]));
+        assert_eq!(parse("Ctrl-X ctrl-e"), Ok(vec![ctrl('X'), ctrl('E')]));
+        assert_eq!(
+            parse("alt-enter"),
+            Ok(vec![KeyEvent(KeyCode::Enter, Modifiers::ALT)])
+        );
+        assert_eq!(
+            parse("shift-tab"),
+            Ok(vec![KeyEvent(KeyCode::BackTab, Modifiers::NONE)])
+        );
+        assert_eq!(parse("f5"), Ok(vec![KeyEvent(KeyCode::F(5), Modifiers::NONE)]));
+        assert_eq!(parse("alt--"), Ok(vec![KeyEvent::alt('-')]));
+        assert!(parse("").is_err());
+        assert!(parse("hyper-a").is_err());
+        assert!(parse("ctrl-tabs").is_err());
+    }
+    #[test]
+    fn test_python_handler() {
+        use super::*;
+        use crate::py;
+        #[pyfunction]
+        fn upper(line: &str, _pos: usize) -> String {
+            line.to_uppercase()
+        }
+        #[pyfunction]
+        fn keep(_line: &str, _pos: usize) {}
+        fn bindings(m: &Bound<PyModule>) -> PyResult<()> {
+            m.add_function(wrap_pyfunction!(upper, m)?)?;
+            m.add_function(wrap_pyfunction!(keep, m)?)
+        }
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            py::register(py, &[py::Module::new("bindings", bindings, Vec::new)])
+                .expect("msg");
+            let call = |name: &str| PythonHandler(name.to_owned()).call(py, "abc", 1);
+            // imported by the handler, not by the shell
+            assert_eq!(call("bindings.upper").expect("msg"), Some("ABC".to_owned()));
+            assert_eq!(call("bindings.keep").expect("msg"), None);
+            assert!(call("bindings.undefined").is_err());
+            assert!(call("undefined").is_err());
+        });
+    }
+}
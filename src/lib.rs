@@ -0,0 +1,45 @@
+//! A Python REPL with embedded Rust APIs using `pyo3`, see [`ShellBuilder`]
+//! to embed it with your own modules, and [`export`] to add Rust functions
+//! and classes to them.
+
+// the paths generated by `export` in this crate
+extern crate self as pyapp;
+
+mod app;
+mod args;
+mod builder;
+mod completion;
+mod config;
+mod display;
+mod export;
+mod fuzzy;
+mod highlight;
+mod indent;
+mod kernel;
+mod keys;
+mod magic;
+mod paste;
+mod py;
+mod semantic;
+mod signature;
+mod stubgen;
+mod theme;
+mod traceback;
+
+pub use app::ExitCode;
+pub use builder::ShellBuilder;
+pub use config::{EditMode, Key, KeyAction};
+pub use py::{clear_screen, exit_shell, ModuleInit};
+pub use pyapp_macros::export;
+pub use pyo3;
+pub use stubgen::FnHint;
+#[doc(hidden)]
+pub use {export::Export, inventory};
+
+const TERMINATE_N: u8 = 2;
+const HISTORY_SIZE: usize = 1000;
+const PROMPT1: &str = "pyapp > ";
+const PROMPT2: &str = " .... > ";
+const PROMPT1_OK: &str = "\x1b[1m\x1b[38;5;39mpyapp\x1b[1;32m > \x1b[m";
+const PROMPT1_ERR: &str = "\x1b[1m\x1b[38;5;39mpyapp\x1b[1;91m > \x1b[m";
+const PROMPT2_OK: &str = "\x1b[1m\x1b[38;5;39m ....\x1b[1;32m > \x1b[m";
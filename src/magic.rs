@@ -0,0 +1,592 @@
+use crate::{config::EditMode, display, keys, py, theme::Theme};
+use pyo3::{
+    exceptions::{PyException, PySystemExit},
+    prelude::*,
+    sync::GILOnceCell,
+    types::{IntoPyDict, PyDict, PyModule, PyType},
+};
+use ruff_python_ast::{Expr, IpyEscapeKind, Mod, Stmt};
+use ruff_python_parser::Parsed;
+use rustyline::history::{History, SearchDirection};
+use std::{
+    io::Write,
+    ops::Range,
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+type LineMagic = fn(Python, &str, &dyn History) -> PyResult<()>;
+type CellMagic = fn(Python, &str, Source) -> PyResult<()>;
+
+/// Code at `line` of `filename`, whose source is in `linecache`
+#[derive(Clone, Copy)]
+struct Source<'a> {
+    code: &'a str,
+    filename: &'a str,
+    line: usize,
+}
+
+impl<'a> Source<'a> {
+    /// `range` of the cell `input`
+    fn new(input: &'a str, filename: &'a str, range: Range<usize>) -> Self {
+        let line = input[..range.start].matches('\n').count() + 1;
+        Self { code: &input[range], filename, line }
+    }
+}
+
+/// `%name args`
+const LINE_MAGICS: [(&str, LineMagic); 11] = [
+    ("cd", cd),
+    ("history", history),
+    ("keys", key_bindings),
+    ("mode", edit_mode),
+    ("pwd", pwd),
+    ("reset", reset),
+    ("run", run_file),
+    ("theme", theme),
+    ("time", time),
+    ("timeit", timeit),
+    ("who", who),
+];
+
+/// `%%name args` at the first line, with the rest of the cell as body
+const CELL_MAGICS: [(&str, CellMagic); 1] = [("time", cell_time)];
+
+/// Run a cell of the shell as `filename`, the top-level magics go to the registry,
+/// `!cmd` goes to the system shell, and the python code between them goes to `exec`
+pub(crate) fn run(
+    py: Python,
+    input: &str,
+    filename: &str,
+    parsed: &Parsed<Mod>,
+    history: &dyn History,
+) -> PyResult<()> {
+    py::cache_source(py, filename, input)?;
+    let source = |range| Source::new(input, filename, range);
+    if let Some(cell) = input.strip_prefix("%%") {
+        let (line, _) = cell.split_once('\n').unwrap_or((cell, ""));
+        let body_start = (line.len() + 3).min(input.len());
+        let (name, args) = split_name(line);
+        return match CELL_MAGICS.iter().find(|(n, _)| *n == name) {
+            Some((_, magic)) => magic(py, args, source(body_start..input.len())),
+            None => Err(usage_error(py, format!("Cell magic `%%{name}` not found."))),
+        };
+    }
+    let body = match parsed.syntax() {
+        // leave the syntax error to python
+        Mod::Module(module) if parsed.errors().is_empty() => &module.body,
+        _ => return py::exec(py, input, filename, 1),
+    };
+    let mut start = 0;
+    for stmt in body {
+        // `targets` is the source of `x = ` for `x = !cmd`
+        let (range, kind, value, targets) = match stmt {
+            Stmt::IpyEscapeCommand(cmd) => (cmd.range, cmd.kind, &cmd.value, None),
+            Stmt::Assign(assign) => match &*assign.value {
+                Expr::IpyEscapeCommand(cmd) => (
+                    assign.range,
+                    cmd.kind,
+                    &cmd.value,
+                    Some(
+                        &input[assign.range.start().to_usize()
+                            ..cmd.range.start().to_usize()],
+                    ),
+                ),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        run_code(py, source(start..range.start().to_usize()))?;
+        start = range.end().to_usize();
+        match (kind, targets) {
+            (IpyEscapeKind::Magic, None) => run_line(py, value, history)?,
+            (IpyEscapeKind::Shell, None) => system(py, &expand(py, value)?)?,
+            (IpyEscapeKind::ShCap, None) => {
+                print(py, &format!("{:?}", getoutput(py, &expand(py, value)?)?))?;
+            }
+            (IpyEscapeKind::Shell | IpyEscapeKind::ShCap, Some(targets)) => {
+                let lines = getoutput(py, &expand(py, value)?)?;
+                assign(py, targets, lines.into_py(py).into_bound(py))?;
+            }
+            (kind, _) => {
+                return Err(usage_error(
+                    py,
+                    format!("`{}` escape is not supported here", kind.as_str()),
+                ))
+            }
+        }
+    }
+    // echo the trailing expression unless it ends with `;`, like IPython
+    if let Some(Stmt::Expr(expr)) = body.last() {
+        let (expr_start, expr_end) =
+            (expr.range.start().to_usize(), expr.range.end().to_usize());
+        if expr_start >= start && !input[expr_end..].trim_start().starts_with(';') {
+            run_code(py, source(start..expr_start))?;
+            // pad to the column of the expression, for the carets of tracebacks
+            let line_start = input[..expr_start].rfind('\n').map_or(0, |idx| idx + 1);
+            let expr = source(expr_start..expr_end);
+            let code = " ".repeat(expr_start - line_start) + expr.code;
+            let value = py::eval(py, &code, filename, expr.line)?;
+            return display::display(py, value);
+        }
+    }
+    run_code(py, source(start..input.len()))
+}
+
+/// `targets = value`, where `targets` is the source of `x = `
+fn assign(py: Python, targets: &str, value: Bound<PyAny>) -> PyResult<()> {
+    const TMP: &str = "__pyapp_escape__";
+    let dict = main_dict(py)?;
+    dict.set_item(TMP, value)?;
+    let res = py.run_bound(&format!("{targets}{TMP}"), None, None);
+    dict.del_item(TMP)?;
+    res
+}
+
+/// `$name` to `str(name)` of `__main__` and `$$` to `$`,
+/// the unknown names are left to the system shell
+fn expand(py: Python, cmd: &str) -> PyResult<String> {
+    let dict = main_dict(py)?;
+    let mut out = String::new();
+    let mut rest = cmd;
+    while let Some(idx) = rest.find('$') {
+        out += &rest[..idx];
+        rest = &rest[idx + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let name = &rest[..len];
+        match dict.get_item(name)? {
+            Some(value) if !name.is_empty() => out += &value.str()?.to_string(),
+            _ => {
+                out.push('$');
+                out += name;
+            }
+        }
+        rest = &rest[len..];
+    }
+    out += rest;
+    Ok(out)
+}
+
+fn shell(cmd: &str) -> Command {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    command.arg(cmd);
+    command
+}
+
+/// Set `_exit_code` of `__main__`, like IPython
+fn set_exit_code(py: Python, status: ExitStatus) -> PyResult<()> {
+    main_dict(py)?.set_item("_exit_code", status.code().unwrap_or(-1))
+}
+
+/// `!cmd`, with the output of the child printed as it goes,
+/// or written at the end when `sys.stdout` is not a file like in the kernel
+fn system(py: Python, cmd: &str) -> PyResult<()> {
+    py::flush_stdio(py);
+    let sys = PyModule::import_bound(py, "sys")?;
+    if sys.getattr("stdout")?.call_method0("fileno").is_ok() {
+        let status = py.allow_threads(|| shell(cmd).status())?;
+        return set_exit_code(py, status);
+    }
+    let output = py.allow_threads(|| shell(cmd).output())?;
+    for (name, bytes) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        sys.getattr(name)?
+            .call_method1("write", (String::from_utf8_lossy(bytes),))?;
+    }
+    set_exit_code(py, output.status)
+}
+
+/// `x = !cmd`, the lines of stdout
+fn getoutput(py: Python, cmd: &str) -> PyResult<Vec<String>> {
+    py::flush_stdio(py);
+    let output = py.allow_threads(|| shell(cmd).stderr(Stdio::inherit()).output())?;
+    set_exit_code(py, output.status)?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(Into::into)
+        .collect())
+}
+
+/// Magic names starting with `prefix`, which starts with `%`
+pub(crate) fn complete(prefix: &str) -> Vec<String> {
+    let names: Vec<String> = if prefix.starts_with("%%") {
+        CELL_MAGICS.iter().map(|(name, _)| format!("%%{name}")).collect()
+    } else {
+        LINE_MAGICS.iter().map(|(name, _)| format!("%{name}")).collect()
+    };
+    names.into_iter().filter(|name| name.starts_with(prefix)).collect()
+}
+
+/// `UsageError` of IPython, for the misuse of a magic
+fn usage_error(py: Python, msg: String) -> PyErr {
+    static USAGE_ERROR: GILOnceCell<Py<PyType>> = GILOnceCell::new();
+    let ty = USAGE_ERROR.get_or_init(py, || {
+        let base = py.get_type_bound::<PyException>();
+        PyErr::new_type_bound(py, "pyapp.UsageError", None, Some(&base), None)
+            .expect("create UsageError")
+    });
+    PyErr::from_type_bound(ty.bind(py).clone(), msg)
+}
+
+fn run_code(py: Python, source: Source) -> PyResult<()> {
+    if source.code.trim().is_empty() {
+        Ok(())
+    } else {
+        py::exec(py, source.code, source.filename, source.line)
+    }
+}
+
+/// `print` of python, so the output follows `sys.stdout`
+fn print(py: Python, s: &str) -> PyResult<()> {
+    PyModule::import_bound(py, "builtins")?.call_method1("print", (s,))?;
+    Ok(())
+}
+
+fn run_line(py: Python, line: &str, history: &dyn History) -> PyResult<()> {
+    let (name, args) = split_name(line);
+    match LINE_MAGICS.iter().find(|(n, _)| *n == name) {
+        Some((_, magic)) => magic(py, args, history),
+        None => Err(usage_error(py, format!("Line magic `%{name}` not found."))),
+    }
+}
+
+fn split_name(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    match line.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (line, ""),
+    }
+}
+
+fn main_dict(py: Python) -> PyResult<Bound<PyDict>> {
+    Ok(PyModule::import_bound(py, "__main__")?.dict())
+}
+
+/// Time with 3 significant digits, like IPython
+fn format_time(secs: f64) -> String {
+    let (value, unit) = if secs >= 1.0 {
+        (secs, "s")
+    } else if secs >= 1e-3 {
+        (secs * 1e3, "ms")
+    } else if secs >= 1e-6 {
+        (secs * 1e6, "µs")
+    } else {
+        (secs * 1e9, "ns")
+    };
+    let precision = if value >= 100.0 {
+        0
+    } else if value >= 10.0 {
+        1
+    } else {
+        2
+    };
+    format!("{value:.precision$} {unit}")
+}
+
+fn print_wall_time(py: Python, elapsed: Duration) -> PyResult<()> {
+    print(py, &format!("Wall time: {}", format_time(elapsed.as_secs_f64())))
+}
+
+fn time(py: Python, args: &str, _history: &dyn History) -> PyResult<()> {
+    const FILENAME: &str = "<time>";
+    py::cache_source(py, FILENAME, args)?;
+    cell_time(py, "", Source { code: args, filename: FILENAME, line: 1 })
+}
+
+fn cell_time(py: Python, _args: &str, body: Source) -> PyResult<()> {
+    let now = Instant::now();
+    let res = run_code(py, body);
+    print_wall_time(py, now.elapsed())?;
+    res
+}
+
+fn timeit(py: Python, args: &str, _history: &dyn History) -> PyResult<()> {
+    const REPEAT: usize = 7;
+    if args.is_empty() {
+        return Err(usage_error(py, "%timeit: missing statement".to_owned()));
+    }
+    let kwargs = [("globals", main_dict(py)?)].into_py_dict_bound(py);
+    let timer = PyModule::import_bound(py, "timeit")?
+        .getattr("Timer")?
+        .call((args,), Some(&kwargs))?;
+    let (number, _): (usize, f64) = timer.call_method0("autorange")?.extract()?;
+    let times: Vec<f64> = timer.call_method1("repeat", (REPEAT, number))?.extract()?;
+    let per_loop: Vec<f64> = times.iter().map(|t| t / number as f64).collect();
+    let mean = per_loop.iter().sum::<f64>() / REPEAT as f64;
+    let std =
+        (per_loop.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / REPEAT as f64).sqrt();
+    print(
+        py,
+        &format!(
+            "{} ± {} per loop (mean ± std. dev. of {REPEAT} runs, {number} loops each)",
+            format_time(mean),
+            format_time(std)
+        ),
+    )
+}
+
+/// `%run file [args]` in a fresh `__main__`, then merge its globals
+fn run_file(py: Python, args: &str, _history: &dyn History) -> PyResult<()> {
+    let argv: Vec<String> = PyModule::import_bound(py, "shlex")?
+        .call_method1("split", (args,))?
+        .extract()?;
+    let Some(file) = argv.first() else {
+        return Err(usage_error(py, "%run: missing file".to_owned()));
+    };
+    let sys = PyModule::import_bound(py, "sys")?;
+    let old_argv = sys.getattr("argv")?;
+    sys.setattr("argv", &argv)?;
+    let kwargs = [("run_name", "__main__")].into_py_dict_bound(py);
+    let res = PyModule::import_bound(py, "runpy")?
+        .getattr("run_path")?
+        .call((file,), Some(&kwargs));
+    sys.setattr("argv", old_argv)?;
+    match res {
+        Ok(globals) => main_dict(py)?.update(globals.downcast::<PyDict>()?.as_mapping()),
+        Err(e) if e.is_instance_of::<PySystemExit>(py) => {
+            match e.value_bound(py).getattr("code")?.extract::<Option<i64>>() {
+                Ok(None | Some(0)) => Ok(()),
+                _ => Err(e),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// `%who [type ...]`, variables, functions and classes of `__main__`
+fn who(py: Python, args: &str, _history: &dyn History) -> PyResult<()> {
+    let types: Vec<&str> = args.split_whitespace().collect();
+    let module_type = PyModule::import_bound(py, "types")?.getattr("ModuleType")?;
+    let mut names = Vec::new();
+    for (name, value) in main_dict(py)? {
+        let name: String = name.extract()?;
+        if name.starts_with('_') || value.is_instance(&module_type)? {
+            continue;
+        }
+        let ty = value.get_type().qualname()?.to_string();
+        if types.is_empty() || types.contains(&ty.as_str()) {
+            names.push(name);
+        }
+    }
+    if names.is_empty() {
+        print(py, "Interactive namespace is empty.")
+    } else {
+        names.sort();
+        print(py, &names.join("\t"))
+    }
+}
+
+/// `%reset [-f]`, delete the names of `__main__` except the dunders
+fn reset(py: Python, args: &str, _history: &dyn History) -> PyResult<()> {
+    if args != "-f" {
+        print!("Once deleted, variables cannot be recovered. Proceed (y/[n])? ");
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Nothing done.");
+            return Ok(());
+        }
+    }
+    let dict = main_dict(py)?;
+    for name in dict.keys() {
+        if !name.extract::<String>()?.starts_with("__") {
+            dict.del_item(name)?;
+        }
+    }
+    Ok(())
+}
+
+/// `%cd [dir | -]`, home by default, `-` for the previous dir
+fn cd(py: Python, args: &str, _history: &dyn History) -> PyResult<()> {
+    let home = || std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    let dir = match args {
+        "" => home(),
+        "-" => match std::env::var_os("OLDPWD") {
+            Some(dir) => dir.into(),
+            None => return Err(usage_error(py, "%cd: OLDPWD not set".to_owned())),
+        },
+        "~" => home(),
+        _ => match args.strip_prefix("~/") {
+            Some(rest) => home().join(rest),
+            None => args.into(),
+        },
+    };
+    let old = std::env::current_dir()?;
+    std::env::set_current_dir(&dir)?;
+    std::env::set_var("OLDPWD", old);
+    print(py, &std::env::current_dir()?.display().to_string())
+}
+
+fn pwd(py: Python, _args: &str, _history: &dyn History) -> PyResult<()> {
+    print(py, &std::env::current_dir()?.display().to_string())
+}
+
+/// `%history [n]`, the last `n` entries, all by default
+fn history(py: Python, args: &str, history: &dyn History) -> PyResult<()> {
+    let len = history.len();
+    let n = if args.is_empty() {
+        len
+    } else {
+        args.parse::<usize>()
+            .map_err(|_| usage_error(py, format!("%history: invalid count '{args}'")))?
+    };
+    for idx in len.saturating_sub(n)..len {
+        if let Ok(Some(sr)) = history.get(idx, SearchDirection::Forward) {
+            print(
+                py,
+                &format!("{:>4}: {}", idx + 1, sr.entry.replace('\n', "\n      ")),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// `%theme name`, switch to a built-in theme or a theme file from the next prompt
+fn theme(py: Python, args: &str, _history: &dyn History) -> PyResult<()> {
+    if let Err(e) = Theme::load(args) {
+        return Err(usage_error(py, format!("%theme: {e}")));
+    }
+    *py::THEME.lock().unwrap() = Some(args.to_owned());
+    Ok(())
+}
+
+/// `%keys`, the key bindings of the config, and the enter key, in the current edit
+/// mode, the other keys have the built-in bindings of rustyline for that mode
+fn key_bindings(py: Python, _args: &str, _history: &dyn History) -> PyResult<()> {
+    let mode = *keys::EDIT_MODE.lock().unwrap();
+    let bindings = keys::BINDINGS.lock().unwrap().clone();
+    let width = bindings
+        .iter()
+        .map(|(name, _)| name.len())
+        .fold("enter".len(), usize::max);
+    let mut out = format!(
+        "edit mode: {}, the keys not listed have the default bindings of rustyline",
+        mode.as_str()
+    );
+    out +=
+        &format!("\n{:width$}  accept, or a newline if the cell is incomplete", "enter");
+    for (name, action) in &bindings {
+        out += &format!("\n{name:width$}  {action}");
+    }
+    print(py, &out)
+}
+
+/// `%mode [emacs | vi]`, the edit mode from the next prompt, toggled by default
+fn edit_mode(py: Python, args: &str, _history: &dyn History) -> PyResult<()> {
+    let mode = {
+        let mut mode = keys::EDIT_MODE.lock().unwrap();
+        *mode = match args {
+            "" => match *mode {
+                EditMode::Emacs => EditMode::Vi,
+                EditMode::Vi => EditMode::Emacs,
+            },
+            "emacs" => EditMode::Emacs,
+            "vi" => EditMode::Vi,
+            _ => return Err(usage_error(py, format!("%mode: unknown mode '{args}'"))),
+        };
+        *mode
+    };
+    print(py, &format!("edit mode: {}", mode.as_str()))
+}
+
+mod test {
+    #[test]
+    fn test_format_time() {
+        use super::*;
+        assert_eq!(format_time(1.5), "1.50 s");
+        assert_eq!(format_time(0.0123), "12.3 ms");
+        assert_eq!(format_time(0.000_456_7), "457 µs");
+        assert_eq!(format_time(5e-8), "50.0 ns");
+        assert_eq!(split_name(" timeit  x + 1 "), ("timeit", "x + 1"));
+        assert_eq!(complete("%ti"), vec!["%time", "%timeit"]);
+        assert_eq!(complete("%%"), vec!["%%time"]);
+        assert_eq!(complete("%th"), vec!["%theme"]);
+    }
+    #[test]
+    fn test_run() {
+        use super::*;
+        use ruff_python_parser::{parse_unchecked, Mode};
+        use rustyline::history::DefaultHistory;
+        pyo3::prepare_freethreaded_python();
+        let history = DefaultHistory::new();
+        Python::with_gil(|py| {
+            let cell = "x = 20\n%time y = x + 1\n%who int\nz = y * 2";
+            run(py, cell, "<cell>", &parse_unchecked(cell, Mode::Ipython), &history)
+                .expect("msg");
+            let z: i64 = main_dict(py)
+                .expect("msg")
+                .get_item("z")
+                .expect("msg")
+                .expect("msg")
+                .extract()
+                .expect("msg");
+            assert_eq!(z, 42);
+            let cell = "%%time\nw = z";
+            run(py, cell, "<cell>", &parse_unchecked(cell, Mode::Ipython), &history)
+                .expect("msg");
+            let cell = "x = 3\nfiles = !echo $x; echo '$$x'\n!true";
+            run(py, cell, "<cell>", &parse_unchecked(cell, Mode::Ipython), &history)
+                .expect("msg");
+            let files: Vec<String> = main_dict(py)
+                .expect("msg")
+                .get_item("files")
+                .expect("msg")
+                .expect("msg")
+                .extract()
+                .expect("msg");
+            assert_eq!(files, vec!["3", "$x"]);
+            let cell = "x * 2\n";
+            run(py, cell, "<cell>", &parse_unchecked(cell, Mode::Ipython), &history)
+                .expect("msg");
+            let echo: i64 =
+                py.eval_bound("_", None, None).expect("msg").extract().expect("msg");
+            assert_eq!(echo, 6);
+            let cell = "%nope";
+            assert!(run(
+                py,
+                cell,
+                "<cell>",
+                &parse_unchecked(cell, Mode::Ipython),
+                &history
+            )
+            .is_err_and(|e| e.to_string().starts_with("UsageError")));
+        });
+    }
+    #[test]
+    fn test_edit_mode() {
+        use super::*;
+        use rustyline::history::DefaultHistory;
+        pyo3::prepare_freethreaded_python();
+        let history = DefaultHistory::new();
+        let mode = || *keys::EDIT_MODE.lock().unwrap();
+        Python::with_gil(|py| {
+            *keys::EDIT_MODE.lock().unwrap() = EditMode::Emacs;
+            edit_mode(py, "", &history).expect("msg");
+            assert_eq!(mode(), EditMode::Vi);
+            edit_mode(py, "", &history).expect("msg");
+            assert_eq!(mode(), EditMode::Emacs);
+            edit_mode(py, "vi", &history).expect("msg");
+            assert_eq!(mode(), EditMode::Vi);
+            edit_mode(py, "vi", &history).expect("msg");
+            assert_eq!(mode(), EditMode::Vi);
+            assert!(edit_mode(py, "nano", &history).is_err());
+            assert_eq!(mode(), EditMode::Vi);
+        });
+    }
+}
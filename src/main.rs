@@ -1,31 +1,87 @@
-use anstyle::{Color, RgbColor};
-
-mod app;
-mod args;
-mod py;
-
-const TERMINATE_N: u8 = 2;
-const PROMPT1: &str = "pyapp > ";
-const PROMPT2: &str = " .... > ";
-const PROMPT1_OK: &str = "\x1b[1m\x1b[38;5;39mpyapp\x1b[1;32m > \x1b[m";
-const PROMPT1_ERR: &str = "\x1b[1m\x1b[38;5;39mpyapp\x1b[1;91m > \x1b[m";
-const PROMPT2_OK: &str = "\n\x1b[1m\x1b[38;5;39m ....\x1b[1;32m > \x1b[m";
-const FUNCTION_COLOR: Color = Color::Rgb(RgbColor(0xDC, 0xBD, 0xFB));
-const BLANK_COLOR: Color = Color::Rgb(RgbColor(0xAD, 0xBA, 0xC7));
-const CLASS_COLOR: Color = Color::Rgb(RgbColor(0xF6, 0x9D, 0x50));
-const KEY1_COLOR: Color = Color::Rgb(RgbColor(0x6C, 0xB6, 0xFF));
-const KEY2_COLOR: Color = Color::Rgb(RgbColor(0xF4, 0x70, 0x67));
-const SYMBOL_COLOR: Color = Color::Rgb(RgbColor(0xFF, 0x93, 0x8A));
-const COMMENT_COLOR: Color = Color::Rgb(RgbColor(0x76, 0x83, 0x90));
-const STRING_COLOR: Color = Color::Rgb(RgbColor(0xA5, 0xD6, 0xFF));
-const UNKNOWN_COLOR: Color = Color::Rgb(RgbColor(0xFF, 0x00, 0x00));
-const BRACKET_COLORS: [Color; 3] = [
-    Color::Rgb(RgbColor(0xFF, 0xFF, 0x00)),
-    Color::Rgb(RgbColor(0xFF, 0x00, 0xFF)),
-    Color::Rgb(RgbColor(0x00, 0xFF, 0xFF)),
-];
-
-fn main() -> app::ExitCode {
-    // // cli/run -m ipykernel_launcher --f=~/.local/share/jupyter/runtime/kernel-v2-28141kSC9njpUbH1b.json
-    app::run(args::Args::parse())
+//! The `pyapp` command, with the demo module `foo` that the hosts of the
+//! library don't get
+
+use pyapp::ShellBuilder;
+use pyo3::prelude::*;
+
+/// return `x + 1`, computed in Rust
+#[pyapp::export(module = "foo")]
+#[pyfunction]
+fn add_one(x: i64) -> i64 {
+    x + 1
+}
+
+/// clear the screen of the shell
+#[pyapp::export(module = "foo")]
+#[pyfunction]
+fn clear() {
+    pyapp::clear_screen();
+}
+
+/// exit the shell with `code`
+#[pyapp::export(module = "foo")]
+#[pyfunction]
+fn exit(code: u8) {
+    pyapp::exit_shell(code);
+}
+
+/// show a demo progress bar
+#[pyapp::export(module = "foo")]
+#[pyfunction]
+fn loading() -> PyResult<()> {
+    // https://github.com/clitic/kdam/blob/main/kdam/examples/rich.rs
+    use kdam::{term, term::Colorizer, tqdm, BarExt, Column, RichProgress, Spinner};
+    use std::io::{stderr, IsTerminal};
+    term::init(stderr().is_terminal());
+    term::hide_cursor()?;
+
+    let mut pb = RichProgress::new(
+        tqdm!(total = 231231231, unit_scale = true, unit_divisor = 1024, unit = "B"),
+        vec![
+            Column::Spinner(Spinner::new(
+                &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"],
+                80.0,
+                1.0,
+            )),
+            Column::Text("[bold blue]?".to_owned()),
+            Column::Animation,
+            Column::Percentage(1),
+            Column::Text("•".to_owned()),
+            Column::CountTotal,
+            Column::Text("•".to_owned()),
+            Column::Rate,
+            Column::Text("•".to_owned()),
+            Column::RemainingTime,
+        ],
+    );
+
+    pb.write("download will begin in 2 seconds".colorize("bold red"))?;
+
+    while pb.pb.elapsed_time() <= 2.0 {
+        pb.refresh()?;
+    }
+
+    pb.replace(1, Column::Text("[bold blue]docker.exe".to_owned()));
+    pb.write("downloading docker.exe".colorize("bold cyan"))?;
+
+    let total_size = 231231231;
+    let mut downloaded = 0;
+
+    while downloaded < total_size {
+        let new = std::cmp::min(downloaded + 2203211, total_size);
+        downloaded = new;
+        pb.update_to(new)?;
+        std::thread::sleep(std::time::Duration::from_millis(12));
+    }
+
+    pb.write("downloaded docker.exe".colorize("bold green"))?;
+    eprintln!();
+    term::show_cursor()?;
+    Ok(())
+}
+
+fn main() -> pyapp::ExitCode {
+    ShellBuilder::new()
+        .init_cmds(["# let's import a python module that impl by Rust!", "import foo"])
+        .run_cli()
 }
@@ -0,0 +1,61 @@
+/// `line` after its prompt and the space that follows
+fn strip<'l>(line: &'l str, prompts: &[&str]) -> Option<&'l str> {
+    let line = line.trim_start();
+    prompts.iter().filter(|prompt| !prompt.is_empty()).find_map(|prompt| {
+        let rest = line.strip_prefix(prompt)?;
+        match rest.strip_prefix(' ') {
+            Some(rest) => Some(rest),
+            None => rest.is_empty().then_some(rest),
+        }
+    })
+}
+
+/// The first line of `text` starts with `>>> ` or `ps1`, as copied from a session
+/// of the REPL, of the shell or from the docs
+pub(crate) fn has_prompts(text: &str, ps1: &str) -> bool {
+    text.lines()
+        .find(|line| !line.trim().is_empty())
+        .and_then(|line| strip(line, &[">>>", ps1.trim()]))
+        .is_some()
+}
+
+/// The cell of a `text` with prompts, the lines after `>>> `, `... `, `ps1` or
+/// `ps2` without them, and the other lines dropped as output
+pub(crate) fn strip_prompts(text: &str, ps1: &str, ps2: &str) -> Option<String> {
+    if !has_prompts(text, ps1) {
+        return None;
+    }
+    let prompts = [">>>", "...", ps1.trim(), ps2.trim()];
+    Some(
+        text.lines()
+            .filter_map(|line| strip(line, &prompts))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+mod test {
+    #[test]
+    fn test_strip_prompts() {
+        use super::*;
+        let (ps1, ps2) = (crate::PROMPT1, crate::PROMPT2);
+        let session = ">>> def f(x):
+...     y = x + 1
+...
+...     return y
+...
+>>> f(1)
+2
+pyapp > print(f(2))
+3
+ .... > ...";
+        assert_eq!(
+            strip_prompts(session, ps1, ps2).as_deref(),
+            Some("def f(x):\n    y = x + 1\n\n    return y\n\nf(1)\nprint(f(2))\n...")
+        );
+        assert_eq!(strip_prompts("\n  >>>\n>>>x", ps1, ps2).as_deref(), Some(""));
+        assert_eq!(strip_prompts("x = 1\n>>> y", ps1, ps2), None);
+        assert_eq!(strip_prompts("... x", ps1, ps2), None);
+        assert!(!has_prompts(">>>x", ps1));
+    }
+}
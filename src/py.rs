@@ -1,91 +1,136 @@
+use crate::{args::Flag, export, stubgen::FnHint};
 use core::sync::atomic::Ordering;
-use pyo3::{prelude::*, types::PyList};
-use std::sync::atomic::{AtomicBool, AtomicU8};
+use pyo3::{ffi, prelude::*, types::PyList};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU8},
+    Mutex,
+};
 
 const PY_FOO: &str =
     include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/py/utils/foo.py"));
 const PY_INIT: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/py/init.py"));
 
+const FOO_FILE: &str = "utils/foo.py";
+const INIT_FILE: &str = "init.py";
+/// file names of the embedded python sources, as shown in tracebacks
+pub(super) const EMBEDDED_FILES: [&str; 2] = [FOO_FILE, INIT_FILE];
+
 pub static CLEAR: AtomicBool = AtomicBool::new(false);
 pub static EXIT: AtomicBool = AtomicBool::new(false);
 pub static EXIT_CODE: AtomicU8 = AtomicU8::new(0);
+/// theme of the next prompt, switched by `%theme`
+pub static THEME: Mutex<Option<String>> = Mutex::new(None);
 
-#[pyfunction]
-fn add_one(x: i64) -> i64 {
-    x + 1
-}
-
-#[pyfunction]
-fn clear() {
+/// Clear the screen of the shell before its next prompt, e.g. from a function
+/// of the host's module
+#[inline]
+pub fn clear_screen() {
     CLEAR.store(true, Ordering::Relaxed);
 }
 
-#[pyfunction]
-fn exit(code: u8) {
+/// Exit the shell with `code` before its next prompt
+#[inline]
+pub fn exit_shell(code: u8) {
     EXIT.store(true, Ordering::Relaxed);
     EXIT_CODE.store(code, Ordering::Relaxed);
 }
 
-#[pyfunction]
-fn loading() -> PyResult<()> {
-    // https://github.com/clitic/kdam/blob/main/kdam/examples/rich.rs
-    use kdam::{term, term::Colorizer, tqdm, BarExt, Column, RichProgress, Spinner};
-    use std::io::{stderr, IsTerminal};
-    term::init(stderr().is_terminal());
-    term::hide_cursor()?;
-
-    let mut pb = RichProgress::new(
-        tqdm!(total = 231231231, unit_scale = true, unit_divisor = 1024, unit = "B"),
-        vec![
-            Column::Spinner(Spinner::new(
-                &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"],
-                80.0,
-                1.0,
-            )),
-            Column::Text("[bold blue]?".to_owned()),
-            Column::Animation,
-            Column::Percentage(1),
-            Column::Text("•".to_owned()),
-            Column::CountTotal,
-            Column::Text("•".to_owned()),
-            Column::Rate,
-            Column::Text("•".to_owned()),
-            Column::RemainingTime,
-        ],
-    );
-
-    pb.write("download will begin in 2 seconds".colorize("bold red"))?;
-
-    while pb.pb.elapsed_time() <= 2.0 {
-        pb.refresh()?;
-    }
-
-    pb.replace(1, Column::Text("[bold blue]docker.exe".to_owned()));
-    pb.write("downloading docker.exe".colorize("bold cyan"))?;
+/// Initializer of a module, such as a `#[pymodule]` function
+pub type ModuleInit = fn(&Bound<'_, PyModule>) -> PyResult<()>;
 
-    let total_size = 231231231;
-    let mut downloaded = 0;
+/// Module of [`ShellBuilder::module`](crate::ShellBuilder::module),
+/// with its type hints
+#[derive(Clone, Copy)]
+pub(crate) struct Module {
+    pub(crate) name: &'static str,
+    pub(crate) init: ModuleInit,
+    pub(crate) hints: fn() -> Vec<FnHint>,
+}
 
-    while downloaded < total_size {
-        let new = std::cmp::min(downloaded + 2203211, total_size);
-        downloaded = new;
-        pb.update_to(new)?;
-        std::thread::sleep(std::time::Duration::from_millis(12));
+impl Module {
+    #[inline]
+    pub(crate) fn new(
+        name: &'static str,
+        init: ModuleInit,
+        hints: fn() -> Vec<FnHint>,
+    ) -> Self {
+        Self { name, init, hints }
     }
+}
 
-    pb.write("downloaded docker.exe".colorize("bold green"))?;
-    eprintln!();
-    term::show_cursor()?;
+/// Dotted names of the registered modules, completed like
+/// `sys.builtin_module_names`
+pub static MODULES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// Create `modules` and the exported ones in `sys.modules`, so they import
+/// like builtin modules. `append_to_inittab!` needs the path of the
+/// `#[pymodule]` at compile time.
+pub(super) fn register(py: Python, modules: &[Module]) -> PyResult<()> {
+    let sys_modules = PyModule::import_bound(py, "sys")?.getattr("modules")?;
+    let mut names = MODULES.lock().unwrap();
+    for name in module_names(modules) {
+        let m = PyModule::new_bound(py, name)?;
+        if let Some(module) = modules.iter().find(|module| module.name == name) {
+            (module.init)(&m)?;
+        }
+        export::add(&m, name)?;
+        if let Some((parent, child)) = name.rsplit_once('.') {
+            sys_modules.get_item(parent)?.setattr(child, &m)?;
+        }
+        sys_modules.set_item(name, m)?;
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
     Ok(())
 }
 
-#[pymodule]
-pub(super) fn foo(foo_module: &Bound<'_, PyModule>) -> PyResult<()> {
-    foo_module.add_function(wrap_pyfunction!(add_one, foo_module)?)?;
-    foo_module.add_function(wrap_pyfunction!(exit, foo_module)?)?;
-    foo_module.add_function(wrap_pyfunction!(clear, foo_module)?)?;
-    foo_module.add_function(wrap_pyfunction!(loading, foo_module)?)?;
-    Ok(())
+/// Names of `modules` and the exported ones, each after its parents
+pub(super) fn module_names(modules: &[Module]) -> Vec<&'static str> {
+    let mut names = Vec::new();
+    for name in modules.iter().map(|module| module.name).chain(export::modules()) {
+        for (end, _) in name.match_indices('.').chain([(name.len(), "")]) {
+            if !names.contains(&&name[..end]) {
+                names.push(&name[..end]);
+            }
+        }
+    }
+    names
+}
+
+/// Same as [`pyo3::prepare_freethreaded_python`], but build the `PyConfig`
+/// from `-I`, `-s` and `-E` the way CPython does.
+pub(super) fn prepare_freethreaded_python(flag: &Flag) {
+    unsafe {
+        if ffi::Py_IsInitialized() != 0 {
+            return;
+        }
+        let mut config = core::mem::MaybeUninit::<ffi::PyConfig>::uninit();
+        ffi::PyConfig_InitPythonConfig(config.as_mut_ptr());
+        let mut config = config.assume_init();
+        // sys.argv is set by `import_args`, and SIGINT is set by `catch_sigint`
+        config.parse_argv = 0;
+        config.install_signal_handlers = 0;
+        if flag.isolate {
+            // also implies `safe_path` when the config is read
+            config.isolated = 1;
+            config.use_environment = 0;
+            config.user_site_directory = 0;
+        }
+        if flag.ignore_env {
+            config.use_environment = 0;
+        }
+        if flag.ignore_site {
+            config.user_site_directory = 0;
+        }
+        let status = ffi::Py_InitializeFromConfig(&config);
+        ffi::PyConfig_Clear(&mut config);
+        if ffi::PyStatus_Exception(status) != 0 {
+            ffi::Py_ExitStatusException(status);
+        }
+        // Release the GIL.
+        ffi::PyEval_SaveThread();
+    }
 }
 
 pub(super) fn import_args(py: Python, py_args: &Vec<String>) -> PyResult<()> {
@@ -93,11 +138,80 @@ pub(super) fn import_args(py: Python, py_args: &Vec<String>) -> PyResult<()> {
 }
 
 pub(super) fn init(py: Python) -> PyResult<()> {
-    PyModule::from_code_bound(py, PY_FOO, "utils/foo.py", "utils.foo")?;
-    PyModule::from_code_bound(py, PY_INIT, "init.py", "")?;
+    PyModule::from_code_bound(py, PY_FOO, FOO_FILE, "utils.foo")?;
+    PyModule::from_code_bound(py, PY_INIT, INIT_FILE, "")?;
     Ok(())
 }
 
+/// Turn SIGINT into `KeyboardInterrupt` of the running code, rustyline reads
+/// Ctrl-C at the prompt as a key so the idle shell never sees the signal
+pub(super) fn catch_sigint(py: Python) -> PyResult<()> {
+    // python only sets signal handlers in the main thread, not in tests
+    let threading = PyModule::import_bound(py, "threading")?;
+    if !threading
+        .call_method0("current_thread")?
+        .is(&threading.call_method0("main_thread")?)
+    {
+        return Ok(());
+    }
+    let signal = PyModule::import_bound(py, "signal")?;
+    signal.call_method1(
+        "signal",
+        (signal.getattr("SIGINT")?, signal.getattr("default_int_handler")?),
+    )?;
+    Ok(())
+}
+
+/// Run `code` in `__main__` as the lines from `line` of `filename`,
+/// so that tracebacks point at its source
+pub(super) fn exec(py: Python, code: &str, filename: &str, line: usize) -> PyResult<()> {
+    compile_run(py, code, filename, line, "exec")?;
+    Ok(())
+}
+
+/// Same as [`exec`] for an expression, return its value
+pub(super) fn eval<'py>(
+    py: Python<'py>,
+    code: &str,
+    filename: &str,
+    line: usize,
+) -> PyResult<Bound<'py, PyAny>> {
+    compile_run(py, code, filename, line, "eval")
+}
+
+/// `mode` of `compile` is also the name of the builtin to run it
+fn compile_run<'py>(
+    py: Python<'py>,
+    code: &str,
+    filename: &str,
+    line: usize,
+    mode: &str,
+) -> PyResult<Bound<'py, PyAny>> {
+    let builtins = PyModule::import_bound(py, "builtins")?;
+    let source = "\n".repeat(line.saturating_sub(1)) + code;
+    let code = builtins.getattr("compile")?.call1((source, filename, mode))?;
+    let globals = PyModule::import_bound(py, "__main__")?.dict();
+    builtins.getattr(mode)?.call1((code, globals))
+}
+
+/// Keep `source` in `linecache` as `filename`, for the code not read from a file
+pub(super) fn cache_source(py: Python, filename: &str, source: &str) -> PyResult<()> {
+    let lines: Vec<&str> = source.split_inclusive('\n').collect();
+    PyModule::import_bound(py, "linecache")?
+        .getattr("cache")?
+        .set_item(filename, (source.len(), py.None(), lines, filename))
+}
+
+pub(super) fn flush_stdio(py: Python) {
+    if let Ok(sys) = PyModule::import_bound(py, "sys") {
+        for name in ["stdout", "stderr"] {
+            if let Ok(stream) = sys.getattr(name) {
+                _ = stream.call_method0("flush");
+            }
+        }
+    }
+}
+
 pub(super) fn is_incomplete_code(
     compile_command: &Bound<PyAny>,
     code: &str,
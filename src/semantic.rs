@@ -0,0 +1,248 @@
+use ruff_python_ast::{
+    visitor::{walk_decorator, walk_expr, walk_parameter, walk_stmt, Visitor},
+    Decorator, Expr, ExprContext, Mod, Parameter, Parameters, Stmt,
+};
+use ruff_python_parser::Parsed;
+use ruff_text_size::{TextRange, TextSize};
+use std::collections::{BTreeMap, HashSet};
+
+/// Names of `builtins` that are highlighted, without the exceptions
+const BUILTINS: &str = "__import__ abs aiter all anext any ascii bin bool breakpoint \
+    bytearray bytes callable chr classmethod compile complex delattr dict dir divmod \
+    enumerate eval exec exit filter float format frozenset getattr globals hasattr hash \
+    help hex id input int isinstance issubclass iter len list locals map max memoryview \
+    min next object oct open ord pow print property quit range repr reversed round set \
+    setattr slice sorted staticmethod str sum tuple type vars zip";
+
+/// What an identifier is, by the syntax tree of the cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    /// defined or called function, and method call
+    Function,
+    /// defined class, and call of a capitalized name
+    Class,
+    /// parameter of the enclosing function or lambda
+    Parameter,
+    Builtin,
+    /// imported module and its alias
+    Module,
+    /// the dotted name of a decorator
+    Decorator,
+    /// attribute access that is not a call
+    Attribute,
+    /// any other name, e.g. `match` or `type` as a variable
+    Name,
+}
+
+/// Roles of the identifiers of a cell, by their ranges
+#[derive(Debug, Default)]
+pub(crate) struct Names {
+    /// end and role of each identifier, by its start
+    roles: BTreeMap<TextSize, (TextSize, Role)>,
+}
+
+impl Names {
+    pub(crate) fn new(parsed: &Parsed<Mod>) -> Self {
+        let mut collector = Collector::default();
+        match parsed.syntax() {
+            Mod::Module(module) => collector.visit_body(&module.body),
+            Mod::Expression(expr) => collector.visit_expr(&expr.body),
+        }
+        collector.names
+    }
+    /// Role of the identifier containing the token at `range`, e.g. `path` of
+    /// `import os.path`
+    pub(crate) fn get(&self, range: TextRange) -> Option<Role> {
+        let (_, &(end, role)) = self.roles.range(..=range.start()).next_back()?;
+        (end >= range.end()).then_some(role)
+    }
+    /// the first role of an identifier is kept, e.g. a decorator over its name
+    fn insert(&mut self, range: TextRange, role: Role) {
+        self.roles.entry(range.start()).or_insert((range.end(), role));
+    }
+}
+
+#[derive(Default)]
+struct Collector<'a> {
+    names: Names,
+    /// parameters of the enclosing functions and lambdas
+    scopes: Vec<Vec<&'a str>>,
+    modules: HashSet<&'a str>,
+    classes: HashSet<&'a str>,
+    functions: HashSet<&'a str>,
+    /// other assigned names, which shadow the builtins
+    bound: HashSet<&'a str>,
+}
+
+impl<'a> Collector<'a> {
+    fn role(&self, id: &str) -> Role {
+        if self.scopes.iter().any(|scope| scope.contains(&id)) {
+            Role::Parameter
+        } else if self.modules.contains(id) {
+            Role::Module
+        } else if self.classes.contains(id) {
+            Role::Class
+        } else if self.functions.contains(id) {
+            Role::Function
+        } else if !self.bound.contains(id) && BUILTINS.split_whitespace().any(|b| b == id)
+        {
+            Role::Builtin
+        } else {
+            Role::Name
+        }
+    }
+    fn decorator(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Call(call) => self.decorator(&call.func),
+            Expr::Attribute(attr) => {
+                self.names.insert(attr.attr.range, Role::Decorator);
+                self.decorator(&attr.value);
+            }
+            Expr::Name(name) => self.names.insert(name.range, Role::Decorator),
+            _ => {}
+        }
+    }
+}
+
+/// A call of an unknown name is taken as a class if it is capitalized
+fn call_role(id: &str) -> Role {
+    if id.starts_with(|c: char| c.is_ascii_uppercase()) {
+        Role::Class
+    } else {
+        Role::Function
+    }
+}
+
+fn parameter_names(parameters: &Parameters) -> Vec<&str> {
+    parameters
+        .posonlyargs
+        .iter()
+        .chain(&parameters.args)
+        .chain(&parameters.kwonlyargs)
+        .map(|p| p.parameter.name.as_str())
+        .chain(
+            parameters
+                .vararg
+                .iter()
+                .chain(&parameters.kwarg)
+                .map(|p| p.name.as_str()),
+        )
+        .collect()
+}
+
+impl<'a> Visitor<'a> for Collector<'a> {
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        match stmt {
+            Stmt::FunctionDef(def) => {
+                self.names.insert(def.name.range, Role::Function);
+                self.functions.insert(def.name.as_str());
+                self.scopes.push(parameter_names(&def.parameters));
+                walk_stmt(self, stmt);
+                self.scopes.pop();
+                return;
+            }
+            Stmt::ClassDef(def) => {
+                self.names.insert(def.name.range, Role::Class);
+                self.classes.insert(def.name.as_str());
+            }
+            Stmt::Import(import) => {
+                for alias in &import.names {
+                    self.names.insert(alias.name.range, Role::Module);
+                    if let Some(asname) = &alias.asname {
+                        self.names.insert(asname.range, Role::Module);
+                        self.modules.insert(asname.as_str());
+                    } else if let Some(first) = alias.name.as_str().split('.').next() {
+                        self.modules.insert(first);
+                    }
+                }
+            }
+            Stmt::ImportFrom(import) => {
+                if let Some(module) = &import.module {
+                    self.names.insert(module.range, Role::Module);
+                }
+            }
+            _ => {}
+        }
+        walk_stmt(self, stmt);
+    }
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Name(name) => {
+                if matches!(name.ctx, ExprContext::Store) {
+                    self.bound.insert(name.id.as_str());
+                }
+                self.names.insert(name.range, self.role(name.id.as_str()));
+            }
+            Expr::Call(call) => match call.func.as_ref() {
+                Expr::Name(name) => {
+                    let role = match self.role(name.id.as_str()) {
+                        Role::Name | Role::Function => call_role(name.id.as_str()),
+                        role => role,
+                    };
+                    self.names.insert(name.range, role);
+                }
+                Expr::Attribute(attr) => {
+                    self.names.insert(attr.attr.range, call_role(attr.attr.as_str()))
+                }
+                _ => {}
+            },
+            Expr::Attribute(attr) => self.names.insert(attr.attr.range, Role::Attribute),
+            Expr::Lambda(lambda) => {
+                self.scopes.push(
+                    lambda.parameters.as_deref().map_or_else(Vec::new, parameter_names),
+                );
+                walk_expr(self, expr);
+                self.scopes.pop();
+                return;
+            }
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+    fn visit_decorator(&mut self, decorator: &'a Decorator) {
+        self.decorator(&decorator.expression);
+        walk_decorator(self, decorator);
+    }
+    fn visit_parameter(&mut self, parameter: &'a Parameter) {
+        self.names.insert(parameter.name.range, Role::Parameter);
+        walk_parameter(self, parameter);
+    }
+}
+
+mod test {
+    #[test]
+    fn test_names() {
+        use super::*;
+        use ruff_python_parser::{parse_unchecked, Mode};
+        let code = "import os.path as p
+@cache
+def f(x, *args):
+    return p.join(x) + len(args) + Foo(y.z)
+match = lambda list: list";
+        let names = Names::new(&parse_unchecked(code, Mode::Ipython));
+        // role of the identifier at the start of `at`
+        let role = |at: &str| {
+            let start = code.find(at).expect("msg");
+            let len = at.find(|c: char| !c.is_alphanumeric() && c != '_');
+            names.get(TextRange::at(
+                TextSize::new(start as u32),
+                TextSize::new(len.unwrap_or(at.len()) as u32),
+            ))
+        };
+        assert_eq!(role("os.path"), Some(Role::Module));
+        assert_eq!(role("path as"), Some(Role::Module));
+        assert_eq!(role("p.join"), Some(Role::Module));
+        assert_eq!(role("cache"), Some(Role::Decorator));
+        assert_eq!(role("f(x"), Some(Role::Function));
+        assert_eq!(role("args):"), Some(Role::Parameter));
+        assert_eq!(role("join"), Some(Role::Function));
+        assert_eq!(role("x) +"), Some(Role::Parameter));
+        assert_eq!(role("len"), Some(Role::Builtin));
+        assert_eq!(role("Foo"), Some(Role::Class));
+        assert_eq!(role("y.z"), Some(Role::Name));
+        assert_eq!(role("z)"), Some(Role::Attribute));
+        assert_eq!(role("match"), Some(Role::Name));
+        assert_eq!(role("list: list"), Some(Role::Parameter));
+        assert_eq!(role("return"), None);
+    }
+}
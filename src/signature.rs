@@ -0,0 +1,479 @@
+use crate::completion;
+use pyo3::{
+    prelude::*,
+    types::{
+        PyBool, PyBytes, PyComplex, PyDict, PyFloat, PyLong, PyModule, PyString, PyTuple,
+        PyType,
+    },
+};
+use ruff_python_ast::Mod;
+use ruff_python_parser::{Parsed, TokenKind};
+use std::ops::Range;
+
+/// The argument under the cursor of a call
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Arg<'l> {
+    /// zero-based index of a positional argument
+    Positional(usize),
+    /// `name=`
+    Keyword(&'l str),
+}
+
+/// The innermost call around `pos` and its argument at `pos`, e.g.
+/// `foo.add_one(1, ` → `(["foo", "add_one"], Positional(1))`,
+/// the callee must be a plain dotted name so that nothing is evaluated
+pub(crate) fn call_at<'l>(
+    parsed: &Parsed<Mod>,
+    line: &'l str,
+    pos: usize,
+) -> Option<(Vec<&'l str>, Arg<'l>)> {
+    let tokens = parsed
+        .tokens()
+        .iter()
+        .map(|token| token.as_tuple())
+        .filter(|(_, range)| range.len().to_u32() != 0 && range.end().to_usize() <= pos)
+        .collect::<Vec<_>>();
+    // open brackets: index of the opener, commas and keyword of the last argument
+    let mut open: Vec<(usize, usize, Option<&str>)> = Vec::new();
+    for (idx, &(kind, _)) in tokens.iter().enumerate() {
+        match kind {
+            TokenKind::Lpar | TokenKind::Lsqb | TokenKind::Lbrace => {
+                open.push((idx, 0, None))
+            }
+            TokenKind::Rpar | TokenKind::Rsqb | TokenKind::Rbrace => {
+                open.pop();
+            }
+            TokenKind::Comma => {
+                if let Some(top) = open.last_mut() {
+                    top.1 += 1;
+                    top.2 = None;
+                }
+            }
+            // `name=` right after the `(` or a `,`
+            TokenKind::Equal if idx >= 2 && tokens[idx - 1].0 == TokenKind::Name => {
+                if let Some(top) = open.last_mut() {
+                    if idx - 2 == top.0 || tokens[idx - 2].0 == TokenKind::Comma {
+                        top.2 = Some(&line[tokens[idx - 1].1]);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let &(start, commas, keyword) = open.last()?;
+    if tokens[start].0 != TokenKind::Lpar {
+        return None;
+    }
+    let mut chain = Vec::new();
+    let mut idx = start;
+    loop {
+        idx = idx.checked_sub(1)?;
+        match tokens[idx] {
+            (TokenKind::Name, range) => chain.push(&line[range]),
+            _ => return None,
+        }
+        match idx.checked_sub(1).map(|i| tokens[i].0) {
+            Some(TokenKind::Dot) => idx -= 1,
+            Some(TokenKind::Def | TokenKind::Class) => return None,
+            _ => break,
+        }
+    }
+    chain.reverse();
+    Some((chain, keyword.map_or(Arg::Positional(commas), Arg::Keyword)))
+}
+
+/// What a signature is read from, by the exact type of the object, so that
+/// its attributes are the builtin ones and reading them runs no python code
+enum Kind {
+    Module,
+    /// a class of the metaclass `type`
+    Class,
+    Function,
+    /// `builtin_function_or_method`, such as a `#[pyfunction]`, and the
+    /// descriptors of the methods of builtin types
+    Builtin,
+    /// bound method, static method or class method, which wraps `__func__`
+    Method {
+        bound: bool,
+    },
+}
+
+fn kind(obj: &Bound<PyAny>) -> Option<Kind> {
+    let py = obj.py();
+    let ty = obj.get_type();
+    if obj.is_instance_of::<PyModule>() {
+        return Some(Kind::Module);
+    }
+    if ty.is(&py.get_type_bound::<PyType>()) {
+        return Some(Kind::Class);
+    }
+    let types = PyModule::import_bound(py, "types").ok()?;
+    let builtins = PyModule::import_bound(py, "builtins").ok()?;
+    let is = |module: &Bound<PyModule>, name: &str| {
+        module.getattr(name).is_ok_and(|t| ty.is(&t))
+    };
+    Some(if is(&types, "FunctionType") {
+        Kind::Function
+    } else if [
+        "BuiltinFunctionType",
+        "MethodDescriptorType",
+        "ClassMethodDescriptorType",
+        "WrapperDescriptorType",
+        "MethodWrapperType",
+    ]
+    .into_iter()
+    .any(|name| is(&types, name))
+    {
+        Kind::Builtin
+    } else if is(&types, "MethodType") || is(&builtins, "classmethod") {
+        Kind::Method { bound: true }
+    } else if is(&builtins, "staticmethod") {
+        Kind::Method { bound: false }
+    } else {
+        return None;
+    })
+}
+
+/// The object at the dotted name `chain`, looked up with `inspect.getattr_static`
+/// and only through modules, classes and functions, so that no `__getattr__`,
+/// property or other descriptor runs
+fn resolve<'py>(py: Python<'py>, chain: &[&str]) -> Option<Bound<'py, PyAny>> {
+    let (root, attrs) = chain.split_first()?;
+    let getattr_static = PyModule::import_bound(py, "inspect")
+        .ok()?
+        .getattr("getattr_static")
+        .ok()?;
+    let mut obj = completion::global(py, root)?;
+    for attr in attrs {
+        if !matches!(kind(&obj)?, Kind::Module | Kind::Class | Kind::Function) {
+            return None;
+        }
+        obj = getattr_static.call1((obj, *attr)).ok()?;
+    }
+    Some(obj)
+}
+
+/// Signature of the callable at the dotted name `chain`, like `(x, /, y=1)`, and
+/// the first line of its docstring. Both are read statically: a python function
+/// from its code object, without its annotations that reading may evaluate, and
+/// with only the defaults of builtin types, the others from `__text_signature__`.
+pub(crate) fn lookup(py: Python, chain: &[&str]) -> Option<(String, Option<String>)> {
+    let (mut obj, mut bound) = (resolve(py, chain)?, false);
+    while let Kind::Method { bound: method } = kind(&obj)? {
+        obj = obj.getattr("__func__").ok()?;
+        bound |= method;
+    }
+    let signature = match kind(&obj)? {
+        Kind::Function => code_signature(&obj, bound)?,
+        Kind::Builtin => text_signature(&obj)?,
+        Kind::Class => {
+            let init = PyModule::import_bound(py, "inspect")
+                .and_then(|inspect| {
+                    inspect.call_method1("getattr_static", (&obj, "__init__"))
+                })
+                .ok()?;
+            match kind(&init) {
+                Some(Kind::Function) => code_signature(&init, true)?,
+                // e.g. a `#[pyclass]` with its `#[new]`
+                _ => text_signature(&obj)?,
+            }
+        }
+        Kind::Module | Kind::Method { .. } => return None,
+    };
+    // the `__doc__` of a class may be a descriptor in its `__dict__`
+    let doc = match kind(&obj)? {
+        Kind::Class => PyModule::import_bound(py, "inspect").and_then(|inspect| {
+            inspect.call_method1("getattr_static", (&obj, "__doc__"))
+        }),
+        _ => obj.getattr("__doc__"),
+    };
+    let doc = doc.ok().and_then(|doc| {
+        let doc = doc.downcast_exact::<PyString>().ok()?.to_string();
+        doc.lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(str::to_owned)
+    });
+    Some((signature, doc))
+}
+
+/// `__text_signature__` without `$self` or `$module`
+fn text_signature(obj: &Bound<PyAny>) -> Option<String> {
+    let signature: String = obj.getattr("__text_signature__").ok()?.extract().ok()?;
+    let (params, rest) = params(&signature)?;
+    Some(format!("({}){rest}", params.join(", ")))
+}
+
+/// Signature of a python function from its code object and defaults
+fn code_signature(func: &Bound<PyAny>, bound: bool) -> Option<String> {
+    const VARARGS: usize = 0x04;
+    const VARKEYWORDS: usize = 0x08;
+    let code = func.getattr("__code__").ok()?;
+    let int = |name| code.getattr(name).ok()?.extract::<usize>().ok();
+    let (argc, posonly, kwonly) =
+        (int("co_argcount")?, int("co_posonlyargcount")?, int("co_kwonlyargcount")?);
+    let flags = int("co_flags")?;
+    let names = code.getattr("co_varnames").ok()?;
+    let names = names.downcast::<PyTuple>().ok()?;
+    let name = |idx: usize| names.get_item(idx).ok()?.extract::<String>().ok();
+    let defaults = func.getattr("__defaults__").ok()?;
+    let defaults = defaults.downcast::<PyTuple>().ok();
+    let kwdefaults = func.getattr("__kwdefaults__").ok()?;
+    let kwdefaults = kwdefaults.downcast::<PyDict>().ok();
+    // `__defaults__` is writable, with more values than parameters
+    let first_default =
+        argc.checked_sub(defaults.map_or(0, |defaults| defaults.len()))?;
+    let mut params = Vec::new();
+    for idx in usize::from(bound)..argc {
+        let mut param = name(idx)?;
+        if let Some(default) = defaults
+            .filter(|_| idx >= first_default)
+            .and_then(|defaults| defaults.get_item(idx - first_default).ok())
+        {
+            param += &format!("={}", default_repr(&default));
+        }
+        params.push(param);
+        if idx + 1 == posonly {
+            params.push("/".to_owned());
+        }
+    }
+    let mut idx = argc + kwonly;
+    if flags & VARARGS != 0 {
+        params.push(format!("*{}", name(idx)?));
+        idx += 1;
+    } else if kwonly > 0 {
+        params.push("*".to_owned());
+    }
+    for idx in argc..argc + kwonly {
+        let mut param = name(idx)?;
+        if let Some(default) =
+            kwdefaults.and_then(|kwdefaults| kwdefaults.get_item(&param).ok().flatten())
+        {
+            param += &format!("={}", default_repr(&default));
+        }
+        params.push(param);
+    }
+    if flags & VARKEYWORDS != 0 {
+        params.push(format!("**{}", name(idx)?));
+    }
+    Some(format!("({})", params.join(", ")))
+}
+
+/// `repr` of a default of a builtin type, `...` for the others whose `__repr__`
+/// may be any code
+fn default_repr(value: &Bound<PyAny>) -> String {
+    let py = value.py();
+    let builtin = value.is_none()
+        || value.is(&py.Ellipsis())
+        || [
+            py.get_type_bound::<PyBool>(),
+            py.get_type_bound::<PyLong>(),
+            py.get_type_bound::<PyFloat>(),
+            py.get_type_bound::<PyComplex>(),
+            py.get_type_bound::<PyString>(),
+            py.get_type_bound::<PyBytes>(),
+        ]
+        .iter()
+        .any(|ty| value.get_type().is(ty));
+    match value.repr() {
+        Ok(repr) if builtin => repr.to_string(),
+        _ => "...".to_owned(),
+    }
+}
+
+/// Parameters at the top level of the parentheses of `signature`, and what
+/// follows them, `$self` of a text signature is dropped
+fn params(signature: &str) -> Option<(Vec<&str>, &str)> {
+    let mut params = Vec::new();
+    let (mut depth, mut quote, mut escaped, mut start) = (0_usize, None, false, 1);
+    if !signature.starts_with('(') {
+        return None;
+    }
+    for (i, c) in signature.char_indices().skip(1) {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ',' | ')') if depth == 0 => {
+                let param = signature[start..i].trim();
+                // the `/` after a dropped `$self`
+                let dropped = param.is_empty()
+                    || param.starts_with('$')
+                    || (param == "/" && params.is_empty());
+                if !dropped {
+                    params.push(param);
+                }
+                if c == ')' {
+                    return Some((params, &signature[i + 1..]));
+                }
+                start = i + 1;
+            }
+            (None, ')' | ']' | '}') => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Index in `params` of the parameter that takes `arg`
+fn current(params: &[&str], arg: &Arg) -> Option<usize> {
+    let (mut positional, mut keyword_only) = (0, false);
+    for (i, param) in params.iter().enumerate() {
+        let name = param.split([':', '=']).next().unwrap_or_default().trim();
+        match arg {
+            _ if *param == "/" => {}
+            _ if *param == "*" => keyword_only = true,
+            Arg::Keyword(_) if param.starts_with("**") => return Some(i),
+            _ if param.starts_with("**") => {}
+            Arg::Positional(_) if param.starts_with('*') => return Some(i),
+            _ if param.starts_with('*') => keyword_only = true,
+            Arg::Positional(n) if !keyword_only => {
+                if positional == *n {
+                    return Some(i);
+                }
+                positional += 1;
+            }
+            Arg::Keyword(keyword) if name == *keyword => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Hint of the call around `pos`: the callee with its signature and the first
+/// line of its docstring, and the range of the parameter of the argument at `pos`
+pub(crate) fn hint(
+    py: Python,
+    parsed: &Parsed<Mod>,
+    line: &str,
+    pos: usize,
+) -> Option<(String, Option<Range<usize>>)> {
+    let (chain, arg) = call_at(parsed, line, pos)?;
+    let (signature, doc) = lookup(py, &chain)?;
+    let (params, rest) = params(&signature)?;
+    let current = current(&params, &arg);
+    let mut hint = format!("{}(", chain.last()?);
+    let mut bold = None;
+    for (i, param) in params.iter().enumerate() {
+        if i > 0 {
+            hint += ", ";
+        }
+        if current == Some(i) {
+            bold = Some(hint.len()..hint.len() + param.len());
+        }
+        hint += param;
+    }
+    hint += ")";
+    hint += rest;
+    if let Some(doc) = doc {
+        hint += &format!(" — {doc}");
+    }
+    Some((hint, bold))
+}
+
+mod test {
+    #[test]
+    fn test_call_at() {
+        use super::*;
+        use ruff_python_parser::{parse_unchecked, Mode};
+        let call = |line: &str| {
+            let parsed = parse_unchecked(line, Mode::Ipython);
+            call_at(&parsed, line, line.len()).map(|(chain, arg)| (chain.join("."), arg))
+        };
+        assert_eq!(
+            call("foo.add_one("),
+            Some(("foo.add_one".into(), Arg::Positional(0)))
+        );
+        assert_eq!(call("f(1, g(2), "), Some(("f".into(), Arg::Positional(2))));
+        assert_eq!(call("print(x, sep="), Some(("print".into(), Arg::Keyword("sep"))));
+        assert_eq!(call("f(x=[1, "), None);
+        assert_eq!(call("f()("), None);
+        assert_eq!(call("def f("), None);
+        assert_eq!(call("f(x)"), None);
+        let signature = "(a, /, b: dict[str, int] = {'x,': 1}, *args, c, **kw) -> int";
+        let (list, rest) = params(signature).expect("msg");
+        assert_eq!(
+            list,
+            ["a", "/", "b: dict[str, int] = {'x,': 1}", "*args", "c", "**kw"]
+        );
+        assert_eq!(rest, " -> int");
+        assert_eq!(current(&list, &Arg::Positional(1)), Some(2));
+        assert_eq!(current(&list, &Arg::Positional(5)), Some(3));
+        assert_eq!(current(&list, &Arg::Keyword("c")), Some(4));
+        assert_eq!(current(&list, &Arg::Keyword("z")), Some(5));
+        assert_eq!(current(&["x", "**kw"], &Arg::Positional(1)), None);
+        assert_eq!(params("($self, /, x)"), Some((vec!["x"], "")));
+    }
+    #[test]
+    fn test_lookup() {
+        use super::*;
+        use crate::py;
+        #[pyfunction]
+        fn add_one(x: i64) -> i64 {
+            x + 1
+        }
+        fn host(m: &Bound<PyModule>) -> PyResult<()> {
+            m.add_function(wrap_pyfunction!(add_one, m)?)?;
+            m.add("version", "1.0")
+        }
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            py::register(py, &[py::Module::new("host", host, Vec::new)]).expect("msg");
+            py.run_bound("import host", None, None).expect("msg");
+            let sig = |chain: &str| {
+                lookup(py, &chain.split('.').collect::<Vec<_>>()).map(|(sig, _)| sig)
+            };
+            assert_eq!(sig("host.add_one"), Some("(x)".into()));
+            assert_eq!(sig("len"), Some("(obj, /)".into()));
+            assert_eq!(sig("host.__name__"), None);
+            assert_eq!(sig("undefined"), None);
+            py.run_bound(
+                "
+def f(a, b=1, /, c='x', *args, d, e=None, **kw):
+    \"\"\"
+
+    first line
+    second line\"\"\"
+def g(x=object(), *, y=...): pass
+class Point:
+    \"\"\"a point\"\"\"
+    def __init__(self, y): pass
+    @classmethod
+    def of(cls, z): pass
+    @property
+    def boom(self):
+        raise RuntimeError
+    def __getattr__(self, name):
+        raise RuntimeError
+point = Point(1)
+def h(x): pass
+h.__defaults__ = (1, 2)
+",
+                None,
+                None,
+            )
+            .expect("msg");
+            assert_eq!(
+                lookup(py, &["f"]),
+                Some((
+                    "(a, b=1, /, c='x', *args, d, e=None, **kw)".into(),
+                    Some("first line".into())
+                ))
+            );
+            assert_eq!(sig("g"), Some("(x=..., *, y=Ellipsis)".into()));
+            assert_eq!(
+                lookup(py, &["Point"]),
+                Some(("(y)".into(), Some("a point".into())))
+            );
+            assert_eq!(sig("Point.of"), Some("(z)".into()));
+            // nothing runs through instances or properties
+            assert_eq!(sig("Point.boom"), None);
+            assert_eq!(sig("point.x"), None);
+            assert_eq!(sig("point.__init__"), None);
+            assert_eq!(sig("h"), None);
+        });
+    }
+}
@@ -0,0 +1,253 @@
+use pyo3::{inspect::types::TypeInfo, prelude::*, types::PyModule};
+
+/// names of `typing` that `TypeInfo` displays
+const TYPING: [&str; 12] = [
+    "Any",
+    "Callable",
+    "Dict",
+    "FrozenSet",
+    "List",
+    "Mapping",
+    "NoReturn",
+    "Optional",
+    "Sequence",
+    "Set",
+    "Tuple",
+    "Union",
+];
+
+/// Type hints of a function, which `__text_signature__` lacks,
+/// `args` are in the order of the parameters
+pub struct FnHint {
+    name: &'static str,
+    args: Vec<TypeInfo>,
+    ret: TypeInfo,
+}
+
+impl FnHint {
+    #[inline]
+    pub fn new(name: &'static str, args: Vec<TypeInfo>, ret: TypeInfo) -> Self {
+        Self { name, args, ret }
+    }
+}
+
+/// Content of the `.pyi` file of `module`
+pub(crate) fn generate(module: &Bound<PyModule>, hints: &[FnHint]) -> PyResult<String> {
+    let py = module.py();
+    let inspect = PyModule::import_bound(py, "inspect")?;
+    let mut body = String::new();
+    for name in module.dir()? {
+        let name: String = name.extract()?;
+        if name.starts_with('_') {
+            continue;
+        }
+        let obj = module.getattr(name.as_str())?;
+        if inspect.call_method1("isroutine", (&obj,))?.is_truthy()? {
+            let hint = hints.iter().find(|hint| hint.name == name);
+            body += &function(&obj, &name, hint, 0)?;
+        } else if inspect.call_method1("isclass", (&obj,))?.is_truthy()? {
+            body += &class(&inspect, &obj, &name)?;
+        } else if !inspect.call_method1("ismodule", (&obj,))?.is_truthy()? {
+            let ty = obj.get_type().qualname()?;
+            body += &format!("{name}: {ty}\n");
+        }
+    }
+    let mut out = format!("# generated by `{} --stubgen`\n", env!("CARGO_PKG_NAME"));
+    if let Some(doc) = doc(module)? {
+        out += &docstring(&doc, 0);
+    }
+    let words: Vec<_> = body.split(|c: char| !c.is_alphanumeric() && c != '_').collect();
+    let typing: Vec<_> = TYPING.into_iter().filter(|name| words.contains(name)).collect();
+    if !typing.is_empty() {
+        out += &format!("\nfrom typing import {}\n", typing.join(", "));
+    }
+    out += "\n";
+    out += &body;
+    Ok(out)
+}
+
+fn class(inspect: &Bound<PyModule>, cls: &Bound<PyAny>, name: &str) -> PyResult<String> {
+    let mut out = format!("\nclass {name}:\n");
+    let mut empty = true;
+    if let Some(doc) = doc(cls)? {
+        out += &docstring(&doc, 1);
+        empty = false;
+    }
+    for member in cls.dir()? {
+        let member: String = member.extract()?;
+        if member.starts_with('_') {
+            continue;
+        }
+        let obj = cls.getattr(member.as_str())?;
+        if inspect.call_method1("isroutine", (&obj,))?.is_truthy()? {
+            out += &function(&obj, &member, None, 1)?;
+        } else {
+            out += &format!("    {member}: Any\n");
+        }
+        empty = false;
+    }
+    if empty {
+        out += "    ...\n";
+    }
+    Ok(out)
+}
+
+fn function(
+    obj: &Bound<PyAny>,
+    name: &str,
+    hint: Option<&FnHint>,
+    indent: usize,
+) -> PyResult<String> {
+    let sig = obj
+        .getattr("__text_signature__")
+        .ok()
+        .and_then(|sig| sig.extract::<String>().ok());
+    let params = match &sig {
+        Some(sig) => params(sig, hint.map(|hint| hint.args.as_slice()).unwrap_or(&[])),
+        None => "*args, **kwargs".to_owned(),
+    };
+    let ret = hint.map(|hint| format!(" -> {}", hint.ret)).unwrap_or_default();
+    let prefix = "    ".repeat(indent);
+    Ok(match doc(obj)? {
+        Some(doc) => {
+            format!("{prefix}def {name}({params}){ret}:\n{}", docstring(&doc, indent + 1))
+        }
+        None => format!("{prefix}def {name}({params}){ret}: ...\n"),
+    })
+}
+
+/// Parameters of `__text_signature__` annotated with `types`
+fn params(sig: &str, types: &[TypeInfo]) -> String {
+    let sig = sig.trim();
+    let sig = sig
+        .strip_prefix('(')
+        .and_then(|sig| sig.strip_suffix(')'))
+        .unwrap_or(sig);
+    let mut types = types.iter();
+    let mut out = Vec::new();
+    for param in split_params(sig) {
+        let param = match param {
+            "$module" => continue,
+            "$self" => "self",
+            "$cls" | "$type" => "cls",
+            param => param,
+        };
+        if matches!(param, "/" | "*" | "self" | "cls") {
+            out.push(param.to_owned());
+            continue;
+        }
+        let (name, default) = match param.split_once('=') {
+            Some((name, default)) => (name.trim(), Some(default.trim())),
+            None => (param, None),
+        };
+        out.push(match (types.next(), default) {
+            (Some(ty), Some(default)) => format!("{name}: {ty} = {default}"),
+            (Some(ty), None) => format!("{name}: {ty}"),
+            (None, Some(default)) => format!("{name}={default}"),
+            (None, None) => name.to_owned(),
+        });
+    }
+    out.join(", ")
+}
+
+/// Split at the commas outside of brackets and quotes
+fn split_params(sig: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let (mut level, mut quote, mut start) = (0, None, 0);
+    for (idx, c) in sig.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(' | '[' | '{') => level += 1,
+            (None, ')' | ']' | '}') => level -= 1,
+            (None, ',') if level == 0 => {
+                out.push(sig[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    out.push(sig[start..].trim());
+    out.retain(|param| !param.is_empty());
+    out
+}
+
+fn doc(obj: &Bound<PyAny>) -> PyResult<Option<String>> {
+    Ok(obj
+        .getattr("__doc__")?
+        .extract::<Option<String>>()?
+        .filter(|doc| !doc.trim().is_empty()))
+}
+
+fn docstring(doc: &str, indent: usize) -> String {
+    let prefix = "    ".repeat(indent);
+    let doc = doc.trim().replace('\\', "\\\\").replace("\"\"\"", "\\\"\\\"\\\"");
+    let mut lines = doc.lines();
+    let mut out = format!("{prefix}\"\"\"{}", lines.next().unwrap_or_default());
+    let mut multi_line = false;
+    for line in lines {
+        out += "\n";
+        if !line.trim().is_empty() {
+            out += &format!("{prefix}{line}");
+        }
+        multi_line = true;
+    }
+    if multi_line {
+        out += &format!("\n{prefix}");
+    }
+    out + "\"\"\"\n"
+}
+
+mod test {
+    #[test]
+    fn test_params() {
+        use super::*;
+        assert_eq!(params("(x)", &[TypeInfo::builtin("int")]), "x: int");
+        assert_eq!(params("($module, x, /, y=1)", &[]), "x, /, y=1");
+        assert_eq!(
+            params(
+                "($self, a, b=(1, 2), *args, c='x,y')",
+                &[TypeInfo::builtin("str"), TypeInfo::Any]
+            ),
+            "self, a: str, b: Any = (1, 2), *args, c='x,y'"
+        );
+    }
+    #[test]
+    fn test_generate() {
+        use super::*;
+        use crate::{export, py};
+        /// return `x + 1`
+        #[pyapp::export(module = "a.b")]
+        #[pyfunction]
+        #[pyo3(name = "add_one")]
+        fn add(x: i64) -> i64 {
+            x + 1
+        }
+        /// a point
+        #[pyapp::export(module = "a.b")]
+        #[pyclass]
+        struct Point {
+            #[pyo3(get)]
+            x: i64,
+        }
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            py::register(py, &[]).expect("msg");
+            // the parent gets the submodule like a package
+            let name: String = py
+                .eval_bound("__import__('a').b.__name__", None, None)
+                .expect("msg")
+                .extract()
+                .expect("msg");
+            assert_eq!(name, "a.b");
+            let module = PyModule::import_bound(py, "a.b").expect("msg");
+            let stub = generate(&module, &export::hints("a.b")).expect("msg");
+            assert!(stub.contains("def add_one(x: int) -> int:\n"));
+            assert!(
+                stub.contains("\nclass Point:\n    \"\"\"a point\"\"\"\n    x: Any\n")
+            );
+            assert!(stub.contains("from typing import Any\n"));
+        });
+    }
+}
@@ -0,0 +1,337 @@
+use anstyle::{Ansi256Color, AnsiColor, Color, RgbColor, Style};
+use serde::{Deserialize, Deserializer};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub(crate) const BUILTIN_THEMES: [&str; 4] =
+    ["dark", "light", "high-contrast", "monochrome"];
+
+#[derive(Error, Debug)]
+pub(crate) enum ThemeError {
+    #[error("unknown theme '{0}', expected one of {BUILTIN_THEMES:?} or a theme file")]
+    Unknown(String),
+    #[error("{0}: {1}")]
+    IO(PathBuf, std::io::Error),
+    #[error("{0}: {1}")]
+    Toml(PathBuf, toml::de::Error),
+    #[error("{0}: brackets should not be empty")]
+    NoBracket(PathBuf),
+}
+
+/// Styles of the highlighter, every field of a theme file is optional
+/// and falls back to the dark theme.
+///
+/// A style is written as `"bold italic #F47067 on 236"`, with effects, then
+/// the foreground color and the background color after `on`. A color is
+/// `#RRGGBB`, an ANSI name like `bright-black`, or a 256-color index.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Theme {
+    /// name, plain text and punctuation
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) blank: Style,
+    /// function name at definition and call
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) function: Style,
+    /// class name at definition and call
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) class: Style,
+    /// function and lambda parameter
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) parameter: Style,
+    /// builtin function and type, like `len` and `int`
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) builtin: Style,
+    /// imported module
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) module: Style,
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) decorator: Style,
+    /// attribute that is not called
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) attribute: Style,
+    /// constant, number, `self` and `super`
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) key1: Style,
+    /// keyword and operator
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) key2: Style,
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) symbol: Style,
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) comment: Style,
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) string: Style,
+    /// unmatched bracket and syntax error hint
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) unknown: Style,
+    /// history and signature hint
+    #[serde(deserialize_with = "de_style")]
+    pub(crate) hint: Style,
+    /// brackets by nesting level, cycled
+    #[serde(deserialize_with = "de_styles")]
+    pub(crate) brackets: Vec<Style>,
+}
+
+impl Default for Theme {
+    #[inline]
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+const fn rgb(r: u8, g: u8, b: u8) -> Style {
+    Style::new().fg_color(Some(Color::Rgb(RgbColor(r, g, b))))
+}
+
+const fn ansi(color: AnsiColor) -> Style {
+    Style::new().fg_color(Some(Color::Ansi(color)))
+}
+
+impl Theme {
+    pub(crate) fn dark() -> Self {
+        Self {
+            blank: rgb(0xAD, 0xBA, 0xC7),
+            function: rgb(0xDC, 0xBD, 0xFB),
+            class: rgb(0xF6, 0x9D, 0x50),
+            parameter: rgb(0xE0, 0xC0, 0x8D).italic(),
+            builtin: rgb(0x96, 0xD0, 0xFF),
+            module: rgb(0x8D, 0xDB, 0x8C),
+            decorator: rgb(0xDC, 0xBD, 0xFB).italic(),
+            attribute: rgb(0xCD, 0xD9, 0xE5),
+            key1: rgb(0x6C, 0xB6, 0xFF),
+            key2: rgb(0xF4, 0x70, 0x67),
+            symbol: rgb(0xFF, 0x93, 0x8A).italic(),
+            comment: rgb(0x76, 0x83, 0x90).italic(),
+            string: rgb(0xA5, 0xD6, 0xFF),
+            unknown: rgb(0xFF, 0x00, 0x00),
+            hint: ansi(AnsiColor::BrightBlack),
+            brackets: vec![
+                rgb(0xFF, 0xFF, 0x00),
+                rgb(0xFF, 0x00, 0xFF),
+                rgb(0x00, 0xFF, 0xFF),
+            ],
+        }
+    }
+    pub(crate) fn light() -> Self {
+        Self {
+            blank: rgb(0x24, 0x29, 0x2F),
+            function: rgb(0x82, 0x50, 0xDF),
+            class: rgb(0x95, 0x38, 0x00),
+            parameter: rgb(0x7D, 0x4E, 0x00).italic(),
+            builtin: rgb(0x09, 0x69, 0xDA),
+            module: rgb(0x11, 0x63, 0x29),
+            decorator: rgb(0x82, 0x50, 0xDF).italic(),
+            attribute: rgb(0x3D, 0x44, 0x4D),
+            key1: rgb(0x05, 0x50, 0xAE),
+            key2: rgb(0xCF, 0x22, 0x2E),
+            symbol: rgb(0xA4, 0x0E, 0x26).italic(),
+            comment: rgb(0x6E, 0x77, 0x81).italic(),
+            string: rgb(0x0A, 0x30, 0x69),
+            unknown: rgb(0xFF, 0x00, 0x00).underline(),
+            hint: rgb(0x8C, 0x95, 0x9F),
+            brackets: vec![
+                rgb(0xB0, 0x88, 0x00),
+                rgb(0xA3, 0x1A, 0xB5),
+                rgb(0x00, 0x7A, 0x87),
+            ],
+        }
+    }
+    /// only the 16 ANSI colors, which follow the palette of the terminal
+    pub(crate) fn high_contrast() -> Self {
+        Self {
+            blank: ansi(AnsiColor::BrightWhite),
+            function: ansi(AnsiColor::BrightMagenta).bold(),
+            class: ansi(AnsiColor::BrightYellow).bold(),
+            parameter: ansi(AnsiColor::BrightYellow).italic(),
+            builtin: ansi(AnsiColor::BrightCyan).bold(),
+            module: ansi(AnsiColor::BrightGreen),
+            decorator: ansi(AnsiColor::BrightMagenta).italic(),
+            attribute: ansi(AnsiColor::White),
+            key1: ansi(AnsiColor::BrightCyan),
+            key2: ansi(AnsiColor::BrightRed).bold(),
+            symbol: ansi(AnsiColor::BrightRed),
+            comment: ansi(AnsiColor::BrightGreen).italic(),
+            string: ansi(AnsiColor::BrightBlue),
+            unknown: ansi(AnsiColor::BrightWhite)
+                .bg_color(Some(Color::Ansi(AnsiColor::Red)))
+                .bold(),
+            hint: ansi(AnsiColor::White).dimmed(),
+            brackets: vec![
+                ansi(AnsiColor::BrightYellow).bold(),
+                ansi(AnsiColor::BrightMagenta).bold(),
+                ansi(AnsiColor::BrightCyan).bold(),
+            ],
+        }
+    }
+    /// effects only, no color
+    pub(crate) fn monochrome() -> Self {
+        Self {
+            blank: Style::new(),
+            function: Style::new().bold(),
+            class: Style::new().bold().underline(),
+            parameter: Style::new().italic(),
+            builtin: Style::new(),
+            module: Style::new(),
+            decorator: Style::new().bold().italic(),
+            attribute: Style::new(),
+            key1: Style::new(),
+            key2: Style::new().bold(),
+            symbol: Style::new(),
+            comment: Style::new().italic().dimmed(),
+            string: Style::new().italic(),
+            unknown: Style::new().invert(),
+            hint: Style::new().dimmed(),
+            brackets: vec![Style::new()],
+        }
+    }
+    pub(crate) fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            "high-contrast" => Some(Self::high_contrast()),
+            "monochrome" => Some(Self::monochrome()),
+            _ => None,
+        }
+    }
+    /// A built-in theme by name, or a theme file
+    pub(crate) fn load(name: &str) -> Result<Self, ThemeError> {
+        if let Some(theme) = Self::builtin(name) {
+            Ok(theme)
+        } else if Path::new(name).is_file() {
+            Self::from_file(Path::new(name))
+        } else {
+            Err(ThemeError::Unknown(name.to_owned()))
+        }
+    }
+    pub(crate) fn from_file(path: &Path) -> Result<Self, ThemeError> {
+        let s =
+            std::fs::read_to_string(path).map_err(|e| ThemeError::IO(path.into(), e))?;
+        Self::parse(&s, path)
+    }
+    fn parse(s: &str, path: &Path) -> Result<Self, ThemeError> {
+        let theme: Self =
+            toml::from_str(s).map_err(|e| ThemeError::Toml(path.into(), e))?;
+        if theme.brackets.is_empty() {
+            return Err(ThemeError::NoBracket(path.into()));
+        }
+        Ok(theme)
+    }
+    /// Style of the bracket at `level`, `unknown` for the negative level
+    pub(crate) fn bracket(&self, level: i32) -> Style {
+        TryInto::<usize>::try_into(level)
+            .map_or(self.unknown, |level| self.brackets[level % self.brackets.len()])
+    }
+}
+
+fn parse_color(s: &str) -> Option<Color> {
+    if let Some(hex) = s.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Color::Rgb(RgbColor(channel(0)?, channel(2)?, channel(4)?)));
+    }
+    if let Ok(idx) = s.parse::<u8>() {
+        return Some(Color::Ansi256(Ansi256Color(idx)));
+    }
+    let (bright, name) = match s.strip_prefix("bright-") {
+        Some(name) => (true, name),
+        None => (false, s),
+    };
+    let color = match name {
+        "black" => AnsiColor::Black,
+        "red" => AnsiColor::Red,
+        "green" => AnsiColor::Green,
+        "yellow" => AnsiColor::Yellow,
+        "blue" => AnsiColor::Blue,
+        "magenta" => AnsiColor::Magenta,
+        "cyan" => AnsiColor::Cyan,
+        "white" => AnsiColor::White,
+        _ => return None,
+    };
+    Some(Color::Ansi(color.bright(bright)))
+}
+
+fn parse_style(s: &str) -> Result<Style, String> {
+    let mut style = Style::new();
+    let mut words = s.split_whitespace();
+    while let Some(word) = words.next() {
+        style = match word {
+            "bold" => style.bold(),
+            "dim" => style.dimmed(),
+            "italic" => style.italic(),
+            "underline" => style.underline(),
+            "reverse" => style.invert(),
+            "strikethrough" => style.strikethrough(),
+            "on" => {
+                let bg = words.next().ok_or("expect a color after 'on'")?;
+                style.bg_color(Some(
+                    parse_color(bg).ok_or_else(|| format!("unknown color '{bg}'"))?,
+                ))
+            }
+            _ => style.fg_color(Some(
+                parse_color(word)
+                    .ok_or_else(|| format!("unknown color or effect '{word}'"))?,
+            )),
+        };
+    }
+    Ok(style)
+}
+
+fn de_style<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Style, D::Error> {
+    parse_style(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+fn de_styles<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Style>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| parse_style(s).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+mod test {
+    #[test]
+    fn test_parse() {
+        use super::*;
+        let path = Path::new("theme.toml");
+        assert_eq!(parse_style(""), Ok(Style::new()));
+        assert_eq!(
+            parse_style("bold #F47067 on bright-black"),
+            Ok(rgb(0xF4, 0x70, 0x67)
+                .bg_color(Some(Color::Ansi(AnsiColor::BrightBlack)))
+                .bold())
+        );
+        assert_eq!(
+            parse_style("italic 39"),
+            Ok(Style::new().fg_color(Some(Color::Ansi256(Ansi256Color(39)))).italic())
+        );
+        assert!(parse_style("#F4706").is_err());
+        assert!(parse_style("blink").is_err());
+        assert!(parse_style("red on").is_err());
+        for name in BUILTIN_THEMES {
+            assert!(Theme::load(name).is_ok());
+        }
+        let theme = Theme::parse(
+            r#"
+comment = "dim"
+brackets = ["red", "green"]
+"#,
+            path,
+        )
+        .expect("msg");
+        assert_eq!(theme.comment, Style::new().dimmed());
+        assert_eq!(theme.string, Theme::dark().string);
+        assert_eq!(theme.bracket(3), ansi(AnsiColor::Green));
+        assert_eq!(theme.bracket(-1), theme.unknown);
+        assert!(matches!(
+            Theme::parse("brackets = []", path),
+            Err(ThemeError::NoBracket(_))
+        ));
+        assert!(matches!(
+            Theme::parse("string = \"purple\"", path),
+            Err(ThemeError::Toml(..))
+        ));
+        assert!(matches!(Theme::load("solarized"), Err(ThemeError::Unknown(_))));
+    }
+}
@@ -0,0 +1,207 @@
+use crate::{highlight, py, theme::Theme};
+use anstyle::Style;
+use pyo3::{prelude::*, types::PyModule};
+use ruff_python_parser::{parse_unchecked, Mode};
+use std::io::IsTerminal;
+
+/// Frames of python between pyapp and the user code, see also `py::EMBEDDED_FILES`
+const INTERNAL_FILES: [&str; 3] = [
+    "<frozen runpy>",
+    "<frozen importlib._bootstrap>",
+    "<frozen importlib._bootstrap_external>",
+];
+
+const CAUSE: &str =
+    "\nThe above exception was the direct cause of the following exception:\n\n";
+const CONTEXT: &str =
+    "\nDuring handling of the above exception, another exception occurred:\n\n";
+
+/// Print the traceback of `err` to stderr, with the source lines highlighted
+/// by `theme` when stderr is a terminal, and keep it in `sys.last_*` like python.
+/// The internal frames are hidden unless `full`.
+pub(crate) fn print(py: Python, err: &PyErr, theme: &Theme, full: bool) {
+    let color = std::io::stderr().is_terminal();
+    match format(py, err, theme, color, full) {
+        Ok(s) => {
+            py::flush_stdio(py);
+            eprint!("{s}");
+        }
+        Err(_) => err.print(py),
+    }
+    let value = err.value_bound(py);
+    if let Ok(sys) = PyModule::import_bound(py, "sys") {
+        _ = sys.setattr("last_type", err.get_type_bound(py));
+        _ = sys.setattr("last_value", value);
+        _ = sys.setattr("last_traceback", err.traceback_bound(py));
+        _ = sys.setattr("last_exc", value);
+    }
+}
+
+/// The chain of `err`, from its `__cause__` or `__context__` to itself
+pub(crate) fn format(
+    py: Python,
+    err: &PyErr,
+    theme: &Theme,
+    color: bool,
+    full: bool,
+) -> PyResult<String> {
+    let value = err.value_bound(py).as_any();
+    // the traceback is kept by `PyErr` rather than the value before python 3.12
+    value.setattr("__traceback__", err.traceback_bound(py))?;
+    let mut chain = vec![(value.clone(), "")];
+    loop {
+        let (last, _) = chain.last().expect("not empty");
+        let cause = last.getattr("__cause__")?;
+        let (next, sep) = if !cause.is_none() {
+            (cause, CAUSE)
+        } else if !last.getattr("__suppress_context__")?.is_truthy()? {
+            (last.getattr("__context__")?, CONTEXT)
+        } else {
+            break;
+        };
+        if next.is_none() || chain.iter().any(|(value, _)| value.is(&next)) {
+            break;
+        }
+        chain.push((next, sep));
+    }
+    let mut out = String::new();
+    for (value, sep) in chain.iter().rev() {
+        out += &exception(value, theme, color, full)?;
+        out += sep;
+    }
+    Ok(out)
+}
+
+fn exception(
+    value: &Bound<PyAny>,
+    theme: &Theme,
+    color: bool,
+    full: bool,
+) -> PyResult<String> {
+    let py = value.py();
+    let traceback = PyModule::import_bound(py, "traceback")?;
+    let linecache = PyModule::import_bound(py, "linecache")?;
+    let tb = value.getattr("__traceback__")?;
+    let mut frames = String::new();
+    let mut hidden = 0;
+    if !tb.is_none() {
+        for frame in traceback.call_method1("extract_tb", (tb,))?.iter()? {
+            let frame = frame?;
+            let filename: String = frame.getattr("filename")?.extract()?;
+            if !full && is_internal(&filename) {
+                hidden += 1;
+                continue;
+            }
+            let lineno: Option<usize> = frame.getattr("lineno")?.extract()?;
+            let name: String = frame.getattr("name")?.extract()?;
+            frames += &format!(
+                "  File \"{filename}\", line {}, in {name}\n",
+                lineno.unwrap_or_default()
+            );
+            let Some(lineno) = lineno else { continue };
+            let line: String =
+                linecache.call_method1("getline", (&filename, lineno))?.extract()?;
+            // `colno` and the rest are new in python 3.11
+            let attr = |name| {
+                frame
+                    .getattr(name)
+                    .ok()
+                    .and_then(|v| v.extract::<Option<usize>>().ok())
+                    .flatten()
+            };
+            let end = match (attr("end_lineno"), attr("end_colno")) {
+                (Some(end_lineno), Some(end_colno)) if end_lineno == lineno => {
+                    Some(end_colno)
+                }
+                (Some(_), Some(_)) => Some(line.trim_end().len()),
+                _ => None,
+            };
+            frames += &source_line(&line, attr("colno").zip(end), theme, color);
+        }
+    }
+    let mut out = String::new();
+    if !frames.is_empty() {
+        out += "Traceback (most recent call last):\n";
+        out += &frames;
+        if hidden > 0 {
+            out += &format!("  [... {hidden} internal frames hidden]\n");
+        }
+    }
+    let lines =
+        traceback.call_method1("format_exception_only", (value.get_type(), value))?;
+    for line in lines.iter()? {
+        out += &line?.extract::<String>()?;
+    }
+    Ok(out)
+}
+
+fn is_internal(filename: &str) -> bool {
+    INTERNAL_FILES.contains(&filename)
+        || py::EMBEDDED_FILES.contains(&filename)
+        || filename.ends_with("runpy.py")
+}
+
+/// The line without indent, and the carets under the byte range `cols` of it
+fn source_line(
+    line: &str,
+    cols: Option<(usize, usize)>,
+    theme: &Theme,
+    color: bool,
+) -> String {
+    let line = line.trim_end();
+    let code = line.trim_start();
+    if code.is_empty() {
+        return String::new();
+    }
+    let indent = line.len() - code.len();
+    let mut out = format!("    {}\n", highlighted(code, theme, color));
+    let Some((start, end)) = cols else { return out };
+    let (start, end) =
+        (start.saturating_sub(indent), end.saturating_sub(indent).min(code.len()));
+    // like python, no carets for the whole line
+    if let (Some(before), Some(under)) = (code.get(..start), code.get(start..end)) {
+        if !under.is_empty() && under.len() < code.len() {
+            let carets = "^".repeat(under.chars().count());
+            let style = if color { theme.unknown } else { Style::new() };
+            out += &format!(
+                "    {}{}{carets}{}\n",
+                " ".repeat(before.chars().count()),
+                style.render(),
+                style.render_reset()
+            );
+        }
+    }
+    out
+}
+
+fn highlighted(code: &str, theme: &Theme, color: bool) -> String {
+    if !color {
+        return code.to_owned();
+    }
+    let parsed = parse_unchecked(code, Mode::Module);
+    let tokens = parsed.tokens();
+    let syntax = highlight::Syntax::new(&parsed);
+    highlight::styled(tokens, code, theme, "", &syntax, None, &[])
+        .map(|(style, s)| format!("{}{s}{}", style.render(), style.render_reset()))
+        .collect()
+}
+
+mod test {
+    #[test]
+    fn test_format() {
+        use super::*;
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let cell = "def f(x):\n    return 1 / x\n\ntry:\n    f(0)\nexcept Exception as e:\n    raise ValueError('bad') from e\n";
+            py::cache_source(py, "<cell-0>", cell).expect("msg");
+            let err = py::exec(py, cell, "<cell-0>", 1).expect_err("msg");
+            let s = format(py, &err, &Theme::default(), false, false).expect("msg");
+            assert!(s.starts_with("Traceback (most recent call last):\n"));
+            assert!(s.contains(
+                "  File \"<cell-0>\", line 2, in f\n    return 1 / x\n           ^^^^^\n"
+            ));
+            assert!(s.contains(CAUSE));
+            assert!(s.ends_with("ValueError: bad\n"));
+        });
+    }
+}
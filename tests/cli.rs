@@ -0,0 +1,141 @@
+use std::process::{Command, Output};
+
+fn pyapp(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pyapp"))
+        .args(args)
+        .env("PYTHONPATH", "/pyapp/test/pythonpath")
+        .output()
+        .expect("failed to run pyapp")
+}
+
+#[test]
+fn interpreter_flags() {
+    let check = |args: &[&str], flags: &str| {
+        let cmd = format!(
+            "import sys\nassert (sys.flags.isolated, sys.flags.ignore_environment, sys.flags.no_user_site) == {flags}, sys.flags"
+        );
+        let out = pyapp(&[args, &["-c", &cmd]].concat());
+        assert!(
+            out.status.success(),
+            "{args:?}: {}",
+            String::from_utf8_lossy(&out.stdout)
+        );
+    };
+    check(&[], "(0, 0, 0)");
+    check(&["-I"], "(1, 1, 1)");
+    check(&["-E"], "(0, 1, 0)");
+    check(&["-s"], "(0, 0, 1)");
+    check(&["-sE"], "(0, 1, 1)");
+}
+
+#[test]
+fn ignore_pythonpath() {
+    let cmd = "import sys\nprint('/pyapp/test/pythonpath' in sys.path)";
+    let out = pyapp(&["-c", cmd]);
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "True\n");
+    for flag in ["-E", "-I"] {
+        let out = pyapp(&[flag, "-c", cmd]);
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "False\n", "{flag}");
+    }
+}
+
+#[test]
+fn broken_config() {
+    let config =
+        std::env::temp_dir().join(format!("pyapp_config_{}.toml", std::process::id()));
+    std::fs::write(&config, "indent = 1").unwrap();
+    let out = pyapp(&["--config", config.to_str().unwrap(), "-c", "print(1)"]);
+    std::fs::remove_file(&config).unwrap();
+    // only the shell fails on its config
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "1\n");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("ignored"));
+}
+
+#[test]
+fn stdin_program() {
+    use std::io::Write;
+    use std::process::Stdio;
+    let run = |args: &[&str]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_pyapp"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to run pyapp");
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(b"import sys\nfor a in sys.argv:\n    print(a)\n")
+            .unwrap();
+        let out = child.wait_with_output().unwrap();
+        assert!(out.status.success());
+        String::from_utf8(out.stdout).unwrap()
+    };
+    // exactly like `python -`, without the prompt or the goodbye of the shell
+    assert_eq!(run(&[]), "-\n");
+    assert_eq!(run(&["-", "arg1"]), "-\narg1\n");
+    assert_eq!(run(&["-"]), "-\n");
+}
+
+#[test]
+fn inspect_after_command() {
+    use std::io::Write;
+    use std::process::Stdio;
+    let run = |cmd: &str| {
+        let args = match cmd.strip_prefix("-m ") {
+            Some(module) => ["-i", "-m", module],
+            None => ["-i", "-c", cmd],
+        };
+        let mut child = Command::new(env!("CARGO_BIN_EXE_pyapp"))
+            .args(args)
+            .env("PYAPP_HISTORY", "")
+            .env("PYTHONPATH", "tests")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to run pyapp");
+        child.stdin.take().unwrap().write_all(b"print(x + 1)\n").unwrap();
+        child.wait_with_output().unwrap()
+    };
+    let out = run("x = 41");
+    assert!(String::from_utf8_lossy(&out.stdout).contains("42"));
+    let out = run("x = 41\n1 / 0");
+    assert!(String::from_utf8_lossy(&out.stdout).contains("42"));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("ZeroDivisionError"));
+    let out = run("-m inspect_module");
+    assert!(String::from_utf8_lossy(&out.stdout).contains("42"));
+    let out = run("-m inspect_module_raise");
+    assert!(String::from_utf8_lossy(&out.stdout).contains("42"));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("raised after x"));
+}
+
+#[test]
+fn interrupt_cell() {
+    let out = Command::new("python3")
+        .args(["tests/shell/interrupt.py", env!("CARGO_BIN_EXE_pyapp")])
+        .output()
+        .expect("failed to run python3");
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+}
+
+#[test]
+fn paste_cell() {
+    let out = Command::new("python3")
+        .args(["tests/shell/paste.py", env!("CARGO_BIN_EXE_pyapp")])
+        .output()
+        .expect("failed to run python3");
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+}
+
+#[test]
+#[ignore = "needs jupyter_client, run with `cargo test -- --ignored`"]
+fn jupyter_kernel() {
+    let out = Command::new("python3")
+        .args(["tests/jupyter/client.py", env!("CARGO_BIN_EXE_pyapp")])
+        .output()
+        .expect("failed to run python3");
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+}
@@ -0,0 +1,2 @@
+# for `pyapp -i -m inspect_module`, whose globals stay in the shell
+x = 41
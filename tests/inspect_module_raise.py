@@ -0,0 +1,3 @@
+# for `pyapp -i -m inspect_module_raise`, whose globals stay in the shell after it raised
+x = 41
+raise ValueError("raised after x")
@@ -0,0 +1,72 @@
+# Drive `pyapp kernel` with jupyter_client: python3 tests/jupyter/client.py <pyapp>
+import subprocess
+import sys
+import time
+
+from jupyter_client import BlockingKernelClient
+from jupyter_client.connect import write_connection_file
+
+TIMEOUT = 10
+
+file, _ = write_connection_file(ip="127.0.0.1")
+kernel = subprocess.Popen([sys.argv[1], "kernel", "--f", file])
+client = BlockingKernelClient(connection_file=file)
+client.load_connection_file()
+client.start_channels()
+try:
+    client.wait_for_ready(timeout=TIMEOUT)
+    outputs = []
+    reply = client.execute_interactive(
+        "import foo\nprint('hi')\nfoo.add_one(41)",
+        timeout=TIMEOUT,
+        output_hook=outputs.append,
+    )
+    assert reply["content"]["status"] == "ok", reply
+    streams = [m["content"]["text"] for m in outputs if m["msg_type"] == "stream"]
+    assert "".join(streams) == "hi\n", outputs
+    results = [m["content"] for m in outputs if m["msg_type"] == "execute_result"]
+    assert results[0]["data"]["text/plain"] == "42", outputs
+
+    reply = client.execute_interactive("1 / 0", timeout=TIMEOUT, output_hook=outputs.append)
+    assert reply["content"]["ename"] == "ZeroDivisionError", reply
+
+    reply = client.complete("foo.add_", reply=True, timeout=TIMEOUT)
+    assert reply["content"]["matches"] == ["add_one"], reply
+    assert reply["content"]["cursor_start"] == 4, reply
+
+    reply = client.inspect("foo.add_one", reply=True, timeout=TIMEOUT)
+    assert reply["content"]["found"], reply
+
+    reply = client.is_complete("for i in range(3):", reply=True, timeout=TIMEOUT)
+    assert reply["content"]["status"] == "incomplete", reply
+    reply = client.is_complete("%time x = 1", reply=True, timeout=TIMEOUT)
+    assert reply["content"]["status"] == "complete", reply
+
+    # `input()` asks the client on the stdin channel, and raises if it can't
+    outputs = []
+    reply = client.execute_interactive(
+        "print(input('name: '))",
+        timeout=TIMEOUT,
+        allow_stdin=True,
+        stdin_hook=lambda msg: client.input("bob"),
+        output_hook=outputs.append,
+    )
+    assert reply["content"]["status"] == "ok", reply
+    streams = [m["content"]["text"] for m in outputs if m["msg_type"] == "stream"]
+    assert "".join(streams) == "bob\n", outputs
+    reply = client.execute_interactive("input()", timeout=TIMEOUT, allow_stdin=False)
+    assert reply["content"]["ename"] == "RuntimeError", reply
+
+    # an `interrupt_request` stops the running cell
+    msg_id = client.execute("while True: pass")
+    time.sleep(1)
+    client.control_channel.send(client.session.msg("interrupt_request", {}))
+    reply = client.get_control_msg(timeout=TIMEOUT)
+    assert reply["msg_type"] == "interrupt_reply", reply
+    reply = client.get_shell_msg(timeout=TIMEOUT)
+    assert reply["parent_header"]["msg_id"] == msg_id, reply
+    assert reply["content"]["ename"] == "KeyboardInterrupt", reply
+finally:
+    client.shutdown()
+    client.stop_channels()
+    assert kernel.wait(TIMEOUT) == 0
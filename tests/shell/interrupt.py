@@ -0,0 +1,51 @@
+# Press Ctrl-C in the shell of `pyapp` on a terminal: python3 tests/shell/interrupt.py <pyapp>
+import os
+import pty
+import select
+import sys
+import time
+
+TIMEOUT = 10
+PROMPT_OK = "\x1b[1;32m > "
+PROMPT_ERR = "\x1b[1;91m > "
+
+pid, fd = pty.fork()
+if pid == 0:
+    os.environ.update(TERM="xterm", PYAPP_HISTORY="", XDG_CONFIG_HOME="/nonexistent")
+    os.execv(sys.argv[1], [sys.argv[1]])
+
+
+def read_until(text):
+    out = ""
+    deadline = time.monotonic() + TIMEOUT
+    while text not in out:
+        left = deadline - time.monotonic()
+        assert left > 0, f"{text!r} not in {out!r}"
+        if select.select([fd], [], [], left)[0]:
+            out += os.read(fd, 4096).decode(errors="replace")
+            # the cursor position asked by rustyline
+            if "\x1b[6n" in out:
+                out = out.replace("\x1b[6n", "")
+                os.write(fd, b"\x1b[1;1R")
+    return out
+
+
+read_until(PROMPT_OK)
+# one interrupt at the prompt, then one while a cell runs
+os.write(fd, b"\x03")
+assert "Need 2 interrupt" in read_until(PROMPT_OK)
+os.write(fd, b"while True: pass\r")
+time.sleep(0.5)
+os.write(fd, b"\x03")
+assert "KeyboardInterrupt" in read_until(PROMPT_ERR)
+
+# the shell survived, and the cell reset the count of interrupts to exit
+os.write(fd, b"print('ali' + 've')\r")
+assert "alive" in read_until(PROMPT_OK)
+os.write(fd, b"\x03")
+assert "Need 2 interrupt" in read_until(PROMPT_OK)
+os.write(fd, b"\x03")
+assert "Need 1 interrupt" in read_until(PROMPT_OK)
+os.write(fd, b"\x03")
+_, status = os.waitpid(pid, 0)
+assert os.waitstatus_to_exitcode(status) == 0, status
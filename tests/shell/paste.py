@@ -0,0 +1,56 @@
+# Paste cells into the shell of `pyapp` on a terminal: python3 tests/shell/paste.py <pyapp>
+import os
+import pty
+import select
+import sys
+import time
+
+TIMEOUT = 10
+PROMPT_OK = "\x1b[1;32m > "
+PASTE_START = b"\x1b[200~"
+PASTE_END = b"\x1b[201~"
+
+pid, fd = pty.fork()
+if pid == 0:
+    os.environ.update(TERM="xterm", PYAPP_HISTORY="", XDG_CONFIG_HOME="/nonexistent")
+    os.execv(sys.argv[1], [sys.argv[1]])
+
+
+def read_until(text):
+    out = ""
+    deadline = time.monotonic() + TIMEOUT
+    while text not in out:
+        left = deadline - time.monotonic()
+        assert left > 0, f"{text!r} not in {out!r}"
+        if select.select([fd], [], [], left)[0]:
+            out += os.read(fd, 4096).decode(errors="replace")
+            # the cursor position asked by rustyline
+            if "\x1b[6n" in out:
+                out = out.replace("\x1b[6n", "")
+                os.write(fd, b"\x1b[1;1R")
+    return out
+
+
+def paste(text):
+    os.write(fd, PASTE_START + text.encode() + PASTE_END + b"\r")
+
+
+read_until(PROMPT_OK)
+# blank lines inside and after a block, the terminal sends its newlines as `\r`
+paste("def f():\r    x = 'one'\r\r    return x\r\r\rprint(f() + ' cell')")
+out = read_until("one cell")
+out += read_until(PROMPT_OK)
+assert "Error" not in out, out
+
+# the same with the prompts of a session
+paste(">>> def g():\r...     x = 'with'\r...\r...     return x\r...\r>>> print(g() + ' prompts')\rcopied output\r")
+out = read_until("with prompts")
+out += read_until(PROMPT_OK)
+assert "Error" not in out, out
+
+for _ in range(2):
+    os.write(fd, b"\x03")
+    read_until(PROMPT_OK)
+os.write(fd, b"\x03")
+_, status = os.waitpid(pid, 0)
+assert os.waitstatus_to_exitcode(status) == 0, status